@@ -0,0 +1,177 @@
+use std::net::{AddrParseError, IpAddr};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseCidrError {
+    #[error("invalid address: {0}")]
+    InvalidAddress(AddrParseError),
+
+    #[error("invalid prefix length: {0}")]
+    InvalidPrefix(ParseIntError),
+
+    #[error("prefix length {0} is too long for the address")]
+    PrefixTooLong(u8),
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    // Syntax: <address>[/<prefix>]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseCidrError::*;
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(InvalidAddress)?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(x) => x.parse().map_err(InvalidPrefix)?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(PrefixTooLong(prefix));
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Which clients may use a listener or upstream and which of them may wake it.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    pub wake: Vec<Cidr>,
+}
+
+impl Access {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let denied = self.deny.iter().any(|x| x.contains(ip));
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|x| x.contains(ip));
+
+        allowed && !denied
+    }
+
+    pub fn may_wake(&self, ip: IpAddr) -> bool {
+        self.wake.is_empty() || self.wake.iter().any(|x| x.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_0_contains_everything_of_its_family() {
+        assert!(cidr("0.0.0.0/0").contains(ip("10.1.2.3")));
+        assert!(cidr("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("fd00::1")));
+
+        assert!(cidr("::/0").contains(ip("fd00::1")));
+        assert!(cidr("::/0").contains(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+    }
+
+    #[test]
+    fn prefix_32_is_a_single_address() {
+        assert!(cidr("10.0.0.1/32").contains(ip("10.0.0.1")));
+        assert!(!cidr("10.0.0.1/32").contains(ip("10.0.0.2")));
+        assert!(!cidr("10.0.0.1/32").contains(ip("10.0.0.0")));
+    }
+
+    #[test]
+    fn prefix_128_is_a_single_address() {
+        assert!(cidr("fd00::1/128").contains(ip("fd00::1")));
+        assert!(!cidr("fd00::1/128").contains(ip("fd00::2")));
+    }
+
+    #[test]
+    fn prefix_boundaries() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.255.255")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.0")));
+        assert!(cidr("fd00::/8").contains(ip("fdff::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe00::1")));
+    }
+
+    #[test]
+    fn missing_prefix_is_a_single_address() {
+        assert_eq!(cidr("10.0.0.1"), cidr("10.0.0.1/32"));
+        assert_eq!(cidr("fd00::1"), cidr("fd00::1/128"));
+    }
+
+    #[test]
+    fn mapped_addresses_match_ipv4_networks() {
+        assert!(cidr("10.0.0.0/24").contains(ip("::ffff:10.0.0.7")));
+    }
+
+    #[test]
+    fn prefix_too_long() {
+        assert_eq!(
+            "10.0.0.0/33".parse::<Cidr>(),
+            Err(ParseCidrError::PrefixTooLong(33))
+        );
+        assert_eq!(
+            "fd00::/129".parse::<Cidr>(),
+            Err(ParseCidrError::PrefixTooLong(129))
+        );
+        assert!(matches!(
+            "10.0.0.0/256".parse::<Cidr>(),
+            Err(ParseCidrError::InvalidPrefix(_))
+        ));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let access = Access {
+            allow: vec![cidr("10.0.0.0/8")],
+            deny: vec![cidr("10.0.0.13")],
+            wake: Vec::new(),
+        };
+
+        assert!(access.allows(ip("10.0.0.1")));
+        assert!(!access.allows(ip("10.0.0.13")));
+        assert!(!access.allows(ip("192.168.0.1")));
+        assert!(access.may_wake(ip("192.168.0.1")));
+    }
+}
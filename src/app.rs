@@ -1,20 +1,36 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::num::NonZero;
 use std::sync::Arc;
+use std::time::Duration;
 
 use eyre::{Context, Result, bail};
 use owo_colors::OwoColorize;
-use tokio::net::TcpListener;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::timeout;
 use tracing::Instrument;
 
+use crate::knock::Knock;
 use crate::mapping::{Mapping, MappingKind};
+use crate::pool::Pool;
+use crate::power::PowerState;
+use crate::proxy;
+use crate::schedule::QuietMode;
 use crate::signal::Signals;
-use crate::upstream::{ConnectOpts, Upstream};
+use crate::transparent::{self, Interception};
+use crate::udp;
+use crate::upstream::{self, Upstream};
+use crate::waker::WakeContext;
 
 #[derive(Debug)]
 pub struct App {
     pub upstream: HashMap<IpAddr, Upstream>,
     pub mappings: Vec<Mapping>,
+    pub knocks: Vec<Knock>,
+    pub pools: HashMap<String, Pool>,
 }
 
 impl App {
@@ -31,22 +47,56 @@ impl App {
         info!("starting...");
         let mut signals = Signals::new().context("failed to register signal handlers")?;
 
-        for mapping in self.mappings.iter().cloned() {
+        for &address in self.upstream.keys() {
+            let app = Arc::clone(&self);
+            let span = error_span!("monitor", upstream = address.to_string());
+
+            tokio::spawn(async move { app.upstream[&address].monitor().await }.instrument(span));
+
+            let app = Arc::clone(&self);
+            let span = error_span!("idle", upstream = address.to_string());
+
+            tokio::spawn(async move { app.upstream[&address].idle_sleep().await }.instrument(span));
+
+            let app = Arc::clone(&self);
+            let span = error_span!("heartbeat", upstream = address.to_string());
+
+            tokio::spawn(
+                async move { app.upstream[&address].heartbeat(&app.upstream).await }
+                    .instrument(span),
+            );
+
+            let app = Arc::clone(&self);
+            let span = error_span!("schedule", upstream = address.to_string());
+
+            tokio::spawn(
+                async move { app.upstream[&address].pre_wake(&app.upstream).await }
+                    .instrument(span),
+            );
+        }
+
+        for index in 0..self.knocks.len() {
+            spawn_knock(index, Arc::clone(&self)).await?;
+        }
+
+        for (index, mapping) in self.mappings.iter().enumerate() {
             let spawn_tunnel =
-                async |from, to, opts| spawn_tunnel(from, to, opts, Arc::clone(&self)).await;
+                async |from, to| spawn_tunnel(from, to, index, Arc::clone(&self)).await;
 
             match mapping.kind {
-                MappingKind::OneToOne { from, to } => spawn_tunnel(from, to, mapping.opts).await?,
+                MappingKind::OneToOne { from, to } => {
+                    spawn_tunnel(from, Destination::Upstream(to)).await?
+                }
 
                 MappingKind::ManyToOne {
                     from_ip,
-                    from_ports,
+                    ref from_ports,
                     to,
                 } => {
-                    for port in from_ports {
+                    for port in from_ports.clone() {
                         let from = SocketAddr::new(from_ip, port);
 
-                        spawn_tunnel(from, to, mapping.opts.clone()).await?
+                        spawn_tunnel(from, Destination::Upstream(to)).await?
                     }
                 }
 
@@ -61,7 +111,39 @@ impl App {
                         let from = SocketAddr::new(from_ip, from_port_range_start + i);
                         let to = SocketAddr::new(to_ip, to_port_range_start + i);
 
-                        spawn_tunnel(from, to, mapping.opts.clone()).await?;
+                        spawn_tunnel(from, Destination::Upstream(to)).await?;
+                    }
+                }
+
+                MappingKind::Pool {
+                    from_ip,
+                    ref from_ports,
+                    ref pool,
+                    port,
+                } => {
+                    for from_port in from_ports.clone() {
+                        let from = SocketAddr::new(from_ip, from_port);
+
+                        spawn_tunnel(from, Destination::Pool(pool.clone(), port)).await?;
+                    }
+                }
+
+                MappingKind::Transparent { from, mode } => {
+                    spawn_tunnel(from, Destination::Original(mode)).await?
+                }
+
+                MappingKind::Udp {
+                    from_ip,
+                    ref from_ports,
+                    to,
+                    session_timeout,
+                    queue,
+                } => {
+                    for port in from_ports.clone() {
+                        let from = SocketAddr::new(from_ip, port);
+
+                        spawn_udp(from, to, session_timeout, queue, index, Arc::clone(&self))
+                            .await?;
                     }
                 }
             }
@@ -73,15 +155,26 @@ impl App {
     }
 }
 
+#[derive(Debug, Clone)]
+enum Destination {
+    Upstream(SocketAddr),
+    Pool(String, u16),
+    Original(Interception),
+}
+
 async fn spawn_tunnel(
     from: SocketAddr,
-    to: SocketAddr,
-    opts: ConnectOpts,
+    to: Destination,
+    mapping: usize,
     app: Arc<App>,
 ) -> Result<()> {
-    let listener = TcpListener::bind(from)
-        .await
-        .with_context(|| format!("failed to bind listener on {}", display!(from)))?;
+    let listener = match &to {
+        Destination::Original(mode) => transparent::bind(from, *mode),
+        _ => TcpListener::bind(from).await,
+    };
+
+    let listener =
+        listener.with_context(|| format!("failed to bind listener on {}", display!(from)))?;
 
     tokio::spawn(async move {
         loop {
@@ -90,36 +183,378 @@ async fn spawn_tunnel(
                 Err(_) => continue,
             };
 
-            let opts = opts.clone();
             let app = Arc::clone(&app);
 
-            let span = error_span!("tunnel", from = addr.to_string(), to = to.to_string());
-            tokio::spawn(
+            let intercepted = matches!(to, Destination::Original(_));
+
+            let to = match &to {
+                Destination::Upstream(x) => *x,
+                Destination::Pool(pool, port) => {
+                    SocketAddr::new(app.pools[pool].pick(&app.upstream), *port)
+                }
+                Destination::Original(mode) => match transparent::original_dst(&a, *mode, from) {
+                    Ok(x) if app.upstream.contains_key(&x.ip()) => x,
+                    Ok(x) => {
+                        let span =
+                            error_span!("tunnel", from = addr.to_string(), to = x.to_string());
+                        span.in_scope(|| info!("destination is not an upstream"));
+                        continue;
+                    }
+                    Err(e) => {
+                        let span = error_span!("tunnel", from = addr.to_string());
+                        span.in_scope(|| warn!("cannot get original destination: {}", display!(e)));
+                        continue;
+                    }
+                },
+            };
+
+            // Intercepted clients think they are connected to the upstream.
+            let local = if intercepted {
+                to
+            } else {
+                a.local_addr().unwrap_or(from)
+            };
+
+            tokio::spawn(async move {
+                let mut a = a;
+
+                let (addr, local) = match &app.mappings[mapping].accept_proxy {
+                    // Checked before reading anything, otherwise any client
+                    // could claim to be any address.
+                    Some(x) if !x.trusts(addr.ip()) => {
+                        let span =
+                            error_span!("tunnel", from = addr.to_string(), to = to.to_string());
+                        span.in_scope(|| info!("peer may not send a PROXY header"));
+                        return;
+                    }
+                    Some(x) => match proxy::accept(&mut a, x.timeout).await {
+                        Ok(Some(x)) => x,
+                        Ok(None) => (addr, local),
+                        Err(e) => {
+                            let span =
+                                error_span!("tunnel", from = addr.to_string(), to = to.to_string());
+                            span.in_scope(|| info!("invalid PROXY header: {}", display!(e)));
+                            return;
+                        }
+                    },
+                    None => (addr, local),
+                };
+
+                let span = error_span!("tunnel", from = addr.to_string(), to = to.to_string());
+
                 async move {
                     info!("connected");
 
+                    let mapping = &app.mappings[mapping];
                     let upstream = app
                         .upstream
                         .get(&to.ip())
                         .expect("upstream should be known");
 
-                    let mut a = a;
-                    let mut b = match upstream.connect(to.port(), &opts).await {
-                        Ok(x) => x,
-                        Err(e) => {
+                    let client = addr.ip();
+                    let Some(may_wake) = admit(mapping, upstream, client) else {
+                        return;
+                    };
+
+                    if let Some(preamble) = &mapping.preamble
+                        && let Err(e) = preamble.verify(&mut a).await
+                    {
+                        info!("client failed to authenticate: {}", display!(e));
+                        return;
+                    }
+
+                    let tunnel = upstream.tunnels.open();
+
+                    // Only wake the upstream up if it cannot be connected to
+                    // right away.
+                    let direct = upstream.try_connect(to.port(), &mapping.opts).await;
+
+                    let early = match (&direct, &mapping.wake_on) {
+                        (Ok(None), Some(wake_on)) if may_wake => match wake_on.wait(&mut a).await {
+                            Ok(x) => x,
+                            Err(e) => {
+                                info!("not waking upstream: {}", display!(e));
+                                return;
+                            }
+                        },
+                        _ => Vec::new(),
+                    };
+
+                    let ctx = WakeContext { client: Some(addr) };
+
+                    let b = async {
+                        match direct? {
+                            Some(x) => Ok(x),
+                            None if !may_wake => {
+                                debug!("client may not wake upstream");
+                                Err(upstream::not_awake())
+                            }
+                            None => {
+                                upstream
+                                    .wake_and_connect(to.port(), &mapping.opts, &ctx, &app.upstream)
+                                    .await
+                            }
+                        }
+                    };
+
+                    let mut b = match (b.await, mapping.fallback) {
+                        (Ok(x), _) => {
+                            info!("{} to upstream", "connected".bright_green());
+                            x
+                        }
+                        (Err(e), Some(fallback)) => {
+                            drop(tunnel);
+
+                            warn!(
+                                "cannot connect to upstream: {}, using fallback {}",
+                                display!(e),
+                                display!(fallback)
+                            );
+
+                            match upstream::connect(fallback, &mapping.opts).await {
+                                Ok(x) => {
+                                    info!("{} to fallback", "connected".bright_green());
+                                    x
+                                }
+                                Err(e) => {
+                                    error!("cannot connect to fallback: {}", display!(e));
+                                    return;
+                                }
+                            }
+                        }
+                        (Err(e), None) => {
                             error!("cannot connect to upstream: {}", display!(e));
                             return;
                         }
                     };
 
-                    info!("{} to upstream", "connected".bright_green());
+                    if let Some(version) = mapping.send_proxy {
+                        let header = proxy::header(version, addr, local);
+
+                        if let Err(e) = b.write_all(&header).await {
+                            error!("cannot write to upstream: {}", display!(e));
+                            return;
+                        }
+                    }
+
+                    if let Err(e) = b.write_all(&early).await {
+                        error!("cannot write to upstream: {}", display!(e));
+                        return;
+                    }
+
                     let _ = tokio::io::copy_bidirectional(&mut a, &mut b).await;
                     info!("disconnected");
                 }
-                .instrument(span),
+                .instrument(span)
+                .await
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn spawn_udp(
+    from: SocketAddr,
+    to: SocketAddr,
+    session_timeout: Duration,
+    queue: NonZero<usize>,
+    mapping: usize,
+    app: Arc<App>,
+) -> Result<()> {
+    let socket = UdpSocket::bind(from)
+        .await
+        .with_context(|| format!("failed to bind udp listener on {}", display!(from)))?;
+
+    let socket = Arc::new(socket);
+
+    tokio::spawn(async move {
+        let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+        let mut buf = vec![0u8; u16::MAX.into()];
+
+        loop {
+            let (n, addr) = match socket.recv_from(&mut buf).await {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!(
+                        "cannot receive datagram on {}: {}",
+                        display!(from),
+                        display!(e)
+                    );
+                    continue;
+                }
+            };
+
+            let span = || error_span!("udp", from = addr.to_string(), to = to.to_string());
+
+            let datagram = match sessions.get(&addr).map(|x| x.try_send(buf[..n].to_vec())) {
+                None => buf[..n].to_vec(),
+                Some(Ok(())) => continue,
+                Some(Err(TrySendError::Full(_))) => {
+                    span().in_scope(|| debug!("queue is full, dropping datagram"));
+                    continue;
+                }
+                Some(Err(TrySendError::Closed(x))) => x,
+            };
+
+            sessions.retain(|_, x| !x.is_closed());
+
+            let (tx, mut datagrams) = mpsc::channel(queue.get());
+            tx.try_send(datagram).expect("a new queue should have room");
+            sessions.insert(addr, tx);
+
+            let app = Arc::clone(&app);
+            let socket = Arc::clone(&socket);
+
+            tokio::spawn(
+                async move {
+                    info!("session started");
+
+                    let mapping = &app.mappings[mapping];
+                    let upstream = app
+                        .upstream
+                        .get(&to.ip())
+                        .expect("upstream should be known");
+
+                    let Some(may_wake) = admit(mapping, upstream, addr.ip()) else {
+                        udp::discard(&mut datagrams, session_timeout).await;
+                        return;
+                    };
+
+                    let tunnel = upstream.tunnels.open();
+                    let ctx = WakeContext { client: Some(addr) };
+
+                    let b = async {
+                        if !may_wake {
+                            debug!("client may not wake upstream");
+
+                            if upstream.power.get() == PowerState::Asleep {
+                                return Err(upstream::not_awake());
+                            }
+                        } else {
+                            upstream.wake_up(&mapping.opts, &ctx, &app.upstream).await?;
+                        }
+
+                        udp::connect(to).await
+                    };
+
+                    let b = match b.await {
+                        Ok(x) => x,
+                        Err(e) => {
+                            drop(tunnel);
+                            error!("cannot reach upstream: {}", display!(e));
+                            udp::discard(&mut datagrams, session_timeout).await;
+                            return;
+                        }
+                    };
+
+                    let mut buf = vec![0u8; u16::MAX.into()];
+
+                    loop {
+                        let relay = async {
+                            tokio::select! {
+                                x = datagrams.recv() => match x {
+                                    Some(x) => b.send(&x).await.map(|_| true),
+                                    None => Ok(false),
+                                },
+                                x = b.recv(&mut buf) => {
+                                    let n = x?;
+                                    socket.send_to(&buf[..n], addr).await.map(|_| true)
+                                }
+                            }
+                        };
+
+                        match timeout(session_timeout, relay).await {
+                            Ok(Ok(true)) => {}
+                            Ok(Ok(false)) | Err(_) => break,
+                            Ok(Err(e)) => debug!("cannot relay datagram: {}", display!(e)),
+                        }
+                    }
+
+                    info!("session expired");
+                }
+                .instrument(span()),
             );
         }
     });
 
     Ok(())
 }
+
+/// Check the access lists, knocks and quiet hours that apply to `client`.
+///
+/// Returns whether the client may wake up the upstream, or `None` if it must
+/// be disconnected.
+fn admit(mapping: &Mapping, upstream: &Upstream, client: IpAddr) -> Option<bool> {
+    if !mapping.access.allows(client) || !upstream.access.allows(client) {
+        info!("client is not allowed");
+        return None;
+    }
+
+    let knocked = match &upstream.grants {
+        Some(x) => x.is_granted(client),
+        None => true,
+    };
+
+    if !knocked {
+        debug!("client has not knocked");
+    }
+
+    let quiet = upstream.schedule.is_quiet();
+
+    if quiet {
+        match upstream.schedule.quiet_mode {
+            QuietMode::Refuse => {
+                info!("quiet hours, refusing client");
+                return None;
+            }
+            QuietMode::AwakeOnly => debug!("quiet hours"),
+        }
+    }
+
+    Some(knocked && !quiet && mapping.access.may_wake(client) && upstream.access.may_wake(client))
+}
+
+async fn spawn_knock(knock: usize, app: Arc<App>) -> Result<()> {
+    let mut ports = app.knocks[knock].ports.clone();
+    ports.sort_unstable();
+    ports.dedup();
+
+    for port in ports {
+        let from = SocketAddr::new(app.knocks[knock].ip, port);
+
+        let listener = TcpListener::bind(from)
+            .await
+            .with_context(|| format!("failed to bind knock listener on {}", display!(from)))?;
+
+        let app = Arc::clone(&app);
+
+        tokio::spawn(async move {
+            let knock = &app.knocks[knock];
+            let grants = app.upstream[&knock.upstream]
+                .grants
+                .as_ref()
+                .expect("knocked upstream should have grants");
+
+            loop {
+                let addr = match listener.accept().await {
+                    Ok((_, addr)) => addr,
+                    Err(_) => continue,
+                };
+
+                let _span = error_span!("knock", from = addr.to_string(), port = port).entered();
+
+                if knock.hit(addr.ip(), port) {
+                    info!(
+                        "{} for {}",
+                        "knock accepted".bright_green(),
+                        display!(knock.grant)
+                    );
+                    grants.grant(addr.ip(), knock.grant);
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use crate::util::{DurationExt, getrandom};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Always wait `delay`.
+    Constant,
+    /// Wait `delay` more after each attempt.
+    Linear,
+    /// Multiply the delay by `factor` after each attempt.
+    Exponential,
+    /// Wait a random time between `delay` and `factor` times the previous
+    /// delay.
+    DecorrelatedJitter,
+}
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub strategy: Strategy,
+    pub delay: Duration,
+    pub factor: f64,
+    pub max_delay: Option<Duration>,
+}
+
+impl Backoff {
+    pub fn delays(&self) -> Delays<'_> {
+        Delays {
+            backoff: self,
+            attempt: 0,
+            prev: self.delay,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Delays<'a> {
+    backoff: &'a Backoff,
+    attempt: u32,
+    prev: Duration,
+}
+
+impl Iterator for Delays<'_> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Backoff {
+            strategy,
+            delay,
+            factor,
+            max_delay,
+        } = *self.backoff;
+
+        // Delays too long to represent are capped at `max_delay`, or else stay
+        // at the previous delay.
+        let overflow = max_delay.unwrap_or(self.prev);
+
+        let next = match strategy {
+            Strategy::Constant => delay,
+            Strategy::Linear => delay
+                .checked_mul(self.attempt.saturating_add(1))
+                .unwrap_or(overflow),
+            Strategy::Exponential => delay
+                .checked_mul_f64(factor.powf(self.attempt.into()))
+                .unwrap_or(overflow),
+            Strategy::DecorrelatedJitter => {
+                let upper = self.prev.checked_mul_f64(factor).unwrap_or(overflow);
+                let spread = upper.saturating_sub(delay);
+
+                delay + spread.checked_mul_f64(random()).unwrap_or(spread)
+            }
+        };
+
+        let next = max_delay.map_or(next, |x| next.min(x));
+
+        self.attempt = self.attempt.saturating_add(1);
+        self.prev = next;
+        Some(next)
+    }
+}
+
+// A random number in [0, 1).
+fn random() -> f64 {
+    let mut buf = [0u8; 8];
+
+    if getrandom(&mut buf).is_err() {
+        return 0.5;
+    }
+
+    (u64::from_ne_bytes(buf) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(strategy: Strategy, delay: u64, factor: f64, max_delay: Option<u64>) -> Backoff {
+        Backoff {
+            strategy,
+            delay: Duration::from_secs(delay),
+            factor,
+            max_delay: max_delay.map(Duration::from_secs),
+        }
+    }
+
+    fn secs(backoff: &Backoff, n: usize) -> Vec<u64> {
+        backoff.delays().take(n).map(|x| x.as_secs()).collect()
+    }
+
+    #[test]
+    fn constant() {
+        let b = backoff(Strategy::Constant, 3, 2.0, None);
+        assert_eq!(secs(&b, 4), [3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn linear() {
+        let b = backoff(Strategy::Linear, 3, 2.0, None);
+        assert_eq!(secs(&b, 4), [3, 6, 9, 12]);
+    }
+
+    #[test]
+    fn exponential() {
+        let b = backoff(Strategy::Exponential, 1, 2.0, None);
+        assert_eq!(secs(&b, 5), [1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn shrinking() {
+        let b = backoff(Strategy::Exponential, 8, 0.5, None);
+        assert_eq!(secs(&b, 4), [8, 4, 2, 1]);
+    }
+
+    #[test]
+    fn max_delay() {
+        let b = backoff(Strategy::Exponential, 1, 2.0, Some(5));
+        assert_eq!(secs(&b, 5), [1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn overflow_is_capped_at_max_delay() {
+        let b = backoff(Strategy::Exponential, 1, 1e300, Some(60));
+        assert_eq!(secs(&b, 3), [1, 60, 60]);
+
+        let b = backoff(Strategy::Linear, u64::MAX, 2.0, Some(60));
+        assert_eq!(secs(&b, 3), [60, 60, 60]);
+    }
+
+    #[test]
+    fn overflow_keeps_the_previous_delay() {
+        let b = backoff(Strategy::Exponential, 1, 1e300, None);
+        assert_eq!(secs(&b, 3), [1, 1, 1]);
+
+        let b = backoff(Strategy::Exponential, 1, 1e10, None);
+        assert_eq!(
+            secs(&b, 4),
+            [1, 10_000_000_000, 10_000_000_000, 10_000_000_000]
+        );
+    }
+
+    #[test]
+    fn decorrelated_jitter() {
+        let b = backoff(Strategy::DecorrelatedJitter, 1, 3.0, Some(20));
+        let mut prev = b.delay;
+
+        for x in b.delays().take(100) {
+            assert!(x >= b.delay);
+            assert!(x <= (prev * 3).min(Duration::from_secs(20)));
+            prev = x;
+        }
+    }
+
+    #[test]
+    fn decorrelated_jitter_overflow() {
+        let b = backoff(Strategy::DecorrelatedJitter, 1, 1e300, None);
+
+        for x in b.delays().take(10) {
+            assert_eq!(x, Duration::from_secs(1));
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use eyre::{Context, Result};
+use owo_colors::OwoColorize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::Instrument;
+
+use crate::preamble::Key;
+use crate::signal::Signals;
+
+/// Forward local connections to `server`, prefixed with a preamble signed
+/// with `key`.
+pub async fn run(listen: SocketAddr, server: String, key: Key) -> Result<()> {
+    let mut signals = Signals::new().context("failed to register signal handlers")?;
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to bind listener on {}", display!(listen)))?;
+
+    info!("forwarding {} to {server}", display!(listen));
+
+    let server: Arc<str> = server.into();
+    let key = Arc::new(key);
+
+    let accept = async {
+        loop {
+            let (mut a, addr) = match listener.accept().await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+
+            let server = Arc::clone(&server);
+            let key = Arc::clone(&key);
+
+            let span = error_span!("tunnel", from = addr.to_string());
+            tokio::spawn(
+                async move {
+                    info!("connected");
+
+                    let mut b = match TcpStream::connect(&*server).await {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("cannot connect to server: {}", display!(e));
+                            return;
+                        }
+                    };
+
+                    let preamble = match key.sign() {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("cannot create preamble: {}", display!(e));
+                            return;
+                        }
+                    };
+
+                    if let Err(e) = b.write_all(&preamble).await {
+                        error!("cannot write to server: {}", display!(e));
+                        return;
+                    }
+
+                    info!("{} to server", "connected".bright_green());
+
+                    let _ = tokio::io::copy_bidirectional(&mut a, &mut b).await;
+                    info!("disconnected");
+                }
+                .instrument(span),
+            );
+        }
+    };
+
+    tokio::select! {
+        _ = accept => {}
+        _ = signals.wait_terminate() => {}
+    }
+
+    info!("exiting...");
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+
+use tracing::Instrument;
+
+use crate::upstream::{self, ConnectOpts, Upstream};
+use crate::waker::WakeContext;
+
+/// Another upstream that must be woken up first. It is ready once `port`
+/// accepts connections.
+#[derive(Debug, Clone, Copy)]
+pub struct Dependency {
+    pub address: IpAddr,
+    pub port: u16,
+    pub required: bool,
+}
+
+impl Upstream {
+    /// Wake up the dependencies of this upstream, and theirs, and wait until
+    /// they are ready.
+    ///
+    /// Dependencies are woken up on their own terms, with the default
+    /// connection options and never during their quiet hours.
+    pub fn wake_dependencies<'a>(
+        &'a self,
+        upstream: &'a HashMap<IpAddr, Upstream>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let opts = ConnectOpts::default();
+
+            for dep in &self.requires {
+                let x = &upstream[&dep.address];
+                let addr = SocketAddr::new(dep.address, dep.port);
+
+                let span = error_span!("dependency", upstream = dep.address.to_string());
+
+                let r = async {
+                    if x.schedule.is_quiet() {
+                        debug!("quiet hours, not waking dependency");
+
+                        return match x.try_connect(dep.port, &opts).await? {
+                            Some(_) => Ok(()),
+                            None => Err(upstream::not_awake()),
+                        };
+                    }
+
+                    x.connect(dep.port, &opts, &WakeContext::default(), upstream)
+                        .await
+                        .map(drop)
+                }
+                .instrument(span)
+                .await;
+
+                match r {
+                    Ok(()) => debug!("dependency {} is ready", display!(addr)),
+                    Err(e) if dep.required => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("dependency {addr} is not ready: {e}"),
+                        ));
+                    }
+                    Err(e) => {
+                        warn!(
+                            "dependency {} is not ready: {}",
+                            display!(addr),
+                            display!(e)
+                        );
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
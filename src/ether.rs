@@ -0,0 +1,42 @@
+use std::io;
+use std::mem::size_of;
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::iface::Interface;
+
+const ETH_P_WOL: u16 = 0x0842;
+
+/// Send `payload` as a layer-2 broadcast frame with EtherType 0x0842 out of
+/// `iface`.
+pub fn send(iface: &Interface, payload: &[u8]) -> io::Result<()> {
+    let index = iface.index()?;
+
+    let protocol = Protocol::from(i32::from(ETH_P_WOL.to_be()));
+    let s = Socket::new(Domain::PACKET, Type::DGRAM, Some(protocol)).map_err(|e| {
+        match e.raw_os_error() {
+            Some(libc::EPERM | libc::EACCES) => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "sending raw ethernet frames requires CAP_NET_RAW",
+            ),
+            _ => e,
+        }
+    })?;
+
+    let ((), addr) = unsafe {
+        SockAddr::try_init(|storage, len| {
+            let ll = (*storage).view_as::<libc::sockaddr_ll>();
+            ll.sll_family = libc::AF_PACKET as u16;
+            ll.sll_protocol = ETH_P_WOL.to_be();
+            ll.sll_ifindex = index as i32;
+            ll.sll_halen = 6;
+            ll.sll_addr[..6].copy_from_slice(&[0xff; 6]);
+
+            *len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+            Ok(())
+        })
+    }?;
+
+    s.send_to(payload, &addr)?;
+    Ok(())
+}
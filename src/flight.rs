@@ -0,0 +1,148 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+type Outcome = Result<(), Arc<io::Error>>;
+
+#[derive(Debug, Default)]
+pub struct SingleFlight {
+    current: Mutex<Option<watch::Receiver<Option<Outcome>>>>,
+}
+
+#[derive(Debug)]
+pub enum Role {
+    Leader(Leader),
+    Follower(Follower),
+}
+
+impl SingleFlight {
+    pub fn join(&self) -> Role {
+        let mut current = self.current.lock().unwrap();
+
+        if let Some(rx) = current.as_ref() {
+            let in_flight = rx.borrow().is_none() && rx.has_changed().is_ok();
+
+            if in_flight {
+                return Role::Follower(Follower(rx.clone()));
+            }
+        }
+
+        let (tx, rx) = watch::channel(None);
+        *current = Some(rx);
+        Role::Leader(Leader(tx))
+    }
+}
+
+#[derive(Debug)]
+pub struct Leader(watch::Sender<Option<Outcome>>);
+
+impl Leader {
+    pub fn land<T>(self, result: &io::Result<T>) {
+        let outcome = match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Arc::new(io::Error::new(e.kind(), e.to_string()))),
+        };
+
+        self.0.send_replace(Some(outcome));
+    }
+}
+
+#[derive(Debug)]
+pub struct Follower(watch::Receiver<Option<Outcome>>);
+
+impl Follower {
+    pub async fn wait(mut self) -> io::Result<()> {
+        let outcome = match self.0.wait_for(Option::is_some).await {
+            Ok(x) => x.clone().expect("outcome should be set"),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "wake attempt was abandoned",
+                ));
+            }
+        };
+
+        outcome.map_err(|e| io::Error::new(e.kind(), e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lead(flight: &SingleFlight) -> Leader {
+        match flight.join() {
+            Role::Leader(x) => x,
+            Role::Follower(_) => panic!("expected to lead"),
+        }
+    }
+
+    fn follow(flight: &SingleFlight) -> Follower {
+        match flight.join() {
+            Role::Follower(x) => x,
+            Role::Leader(_) => panic!("expected to follow"),
+        }
+    }
+
+    #[tokio::test]
+    async fn followers_get_the_result() {
+        let flight = SingleFlight::default();
+
+        let leader = lead(&flight);
+        let a = follow(&flight);
+        let b = follow(&flight);
+
+        leader.land(&Ok(()));
+
+        a.wait().await.unwrap();
+        b.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn followers_get_the_error() {
+        let flight = SingleFlight::default();
+
+        let leader = lead(&flight);
+        let a = follow(&flight);
+        let b = follow(&flight);
+
+        let wait = tokio::spawn(a.wait());
+        leader.land::<()>(&Err(io::Error::new(io::ErrorKind::TimedOut, "too slow")));
+
+        let e = wait.await.unwrap().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(e.to_string(), "too slow");
+
+        let e = b.wait().await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn dropped_leader() {
+        let flight = SingleFlight::default();
+
+        let leader = lead(&flight);
+        let a = follow(&flight);
+
+        drop(leader);
+
+        let e = a.wait().await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+
+        // Nobody is waking up anymore, so the next one leads.
+        let _ = lead(&flight);
+    }
+
+    #[test]
+    fn new_leader_after_landing() {
+        let flight = SingleFlight::default();
+
+        lead(&flight).land(&Ok(()));
+        let leader = lead(&flight);
+
+        let _ = follow(&flight);
+        leader.land(&Ok(()));
+        let _ = lead(&flight);
+    }
+}
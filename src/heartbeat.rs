@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::{MissedTickBehavior, interval, timeout};
+
+use crate::power::PowerState;
+use crate::upstream::Upstream;
+use crate::waker::WakeContext;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatKind {
+    Tcp(u16),
+    Wake,
+}
+
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub kind: HeartbeatKind,
+    pub interval: Duration,
+}
+
+impl Upstream {
+    pub async fn heartbeat(&self, upstream: &HashMap<IpAddr, Upstream>) {
+        let Some(heartbeat) = &self.heartbeat else {
+            return;
+        };
+
+        let mut tunnels = self.tunnels.subscribe();
+
+        loop {
+            let _ = tunnels.wait_for(|&n| n != 0).await;
+            debug!("keeping upstream awake");
+
+            let mut ticks = interval(heartbeat.interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticks.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {
+                        if let Err(e) = self.beat(heartbeat, upstream).await {
+                            warn!("failed to keep upstream awake: {}", display!(e));
+                        }
+                    }
+
+                    _ = async { drop(tunnels.wait_for(|&n| n == 0).await) } => {
+                        debug!("no more tunnels, letting upstream be");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn beat(
+        &self,
+        heartbeat: &Heartbeat,
+        upstream: &HashMap<IpAddr, Upstream>,
+    ) -> io::Result<()> {
+        match heartbeat.kind {
+            HeartbeatKind::Tcp(port) => {
+                timeout(heartbeat.interval, TcpStream::connect((self.address, port)))
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+                    .map(drop)
+            }
+            HeartbeatKind::Wake => {
+                // An awake upstream only needs to be kept awake, its
+                // dependencies were woken up along with it. Otherwise, they
+                // are woken up under their own policy. Cutting them off at the
+                // interval would abandon their wake midway.
+                if self.power.get() != PowerState::Awake {
+                    self.wake_dependencies(upstream).await?;
+                }
+
+                self.wake(&WakeContext::default()).await
+            }
+        }
+    }
+}
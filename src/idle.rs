@@ -0,0 +1,44 @@
+use tokio::time::sleep;
+
+use crate::power::PowerState;
+use crate::upstream::Upstream;
+
+impl Upstream {
+    pub async fn idle_sleep(&self) {
+        let Some(idle) = self.idle_sleep else {
+            return;
+        };
+
+        let mut tunnels = self.tunnels.subscribe();
+        let mut power = self.power.subscribe();
+
+        loop {
+            // Only count down once the upstream has been seen awake, so that a
+            // host wolly knows nothing about is not put to sleep.
+            let busy = *tunnels.borrow_and_update() != 0;
+            let awake = *power.borrow_and_update() == PowerState::Awake;
+
+            if busy || !awake {
+                tokio::select! {
+                    _ = tunnels.changed() => {}
+                    _ = power.changed() => {}
+                }
+                continue;
+            }
+
+            debug!("no active tunnels, going to sleep in {}", display!(idle));
+
+            tokio::select! {
+                _ = sleep(idle) => {
+                    if let Err(e) = self.sleep().await {
+                        warn!("failed to put upstream to sleep: {}", display!(e));
+                    }
+                }
+
+                _ = tunnels.changed() => {
+                    debug!("new tunnel, not going to sleep");
+                }
+            }
+        }
+    }
+}
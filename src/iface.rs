@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+use std::ffi::CString;
+use std::num::NonZero;
+use std::str::FromStr;
+use std::{fmt, io};
+
+use socket2::SockRef;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interface {
+    Name(String),
+    Index(NonZero<u32>),
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(x) => f.write_str(x),
+            Self::Index(x) => write!(f, "#{x}"),
+        }
+    }
+}
+
+impl Interface {
+    pub fn index(&self) -> io::Result<u32> {
+        let name = match self {
+            Self::Index(x) => return Ok(x.get()),
+            Self::Name(x) => x,
+        };
+
+        let c_name = CString::new(name.as_str())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+
+        match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such interface '{name}'"),
+            )),
+            x => Ok(x),
+        }
+    }
+
+    pub fn bind(&self, s: SockRef<'_>, ipv6: bool) -> io::Result<()> {
+        let r = match self {
+            Self::Name(x) => s.bind_device(Some(x.as_bytes())),
+            Self::Index(x) if ipv6 => s.bind_device_by_index_v6(Some(*x)),
+            Self::Index(x) => s.bind_device_by_index_v4(Some(*x)),
+        };
+
+        r.map_err(|e| io::Error::new(e.kind(), format!("failed to bind to interface {self}: {e}")))
+    }
+}
+
+impl FromStr for Interface {
+    type Err = Infallible;
+
+    // Syntax: <name> | <index>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(x) => Ok(Self::Index(x)),
+            Err(_) => Ok(Self::Name(s.to_owned())),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// A sequence of ports clients must connect to, in order, before they may
+/// wake `upstream`.
+#[derive(Debug)]
+pub struct Knock {
+    pub ip: IpAddr,
+    pub ports: Vec<u16>,
+    pub upstream: IpAddr,
+    pub window: Duration,
+    pub grant: Duration,
+    pub progress: Mutex<HashMap<IpAddr, Progress>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    next: usize,
+    started: Instant,
+}
+
+impl Knock {
+    /// Record a knock from `client` on `port`. Returns `true` when it
+    /// completes the sequence.
+    pub fn hit(&self, client: IpAddr, port: u16) -> bool {
+        let now = Instant::now();
+        let client = client.to_canonical();
+
+        let mut progress = self.progress.lock().unwrap();
+        progress.retain(|_, x| now.duration_since(x.started) <= self.window);
+
+        let next = match progress.get(&client) {
+            Some(x) if self.ports[x.next] == port => Some(Progress {
+                next: x.next + 1,
+                ..*x
+            }),
+            _ if self.ports[0] == port => Some(Progress {
+                next: 1,
+                started: now,
+            }),
+            _ => None,
+        };
+
+        match next {
+            Some(x) if x.next == self.ports.len() => {
+                progress.remove(&client);
+                true
+            }
+            Some(x) => {
+                debug!("knock {}/{}", x.next, self.ports.len());
+                progress.insert(client, x);
+                false
+            }
+            None => {
+                debug!("wrong knock, starting over");
+                progress.remove(&client);
+                false
+            }
+        }
+    }
+}
+
+/// Clients that have knocked and until when they may wake an upstream.
+#[derive(Debug, Default)]
+pub struct Grants(Mutex<HashMap<IpAddr, Instant>>);
+
+impl Grants {
+    pub fn grant(&self, client: IpAddr, duration: Duration) {
+        let now = Instant::now();
+
+        let mut grants = self.0.lock().unwrap();
+        grants.retain(|_, &mut until| until > now);
+        grants.insert(client.to_canonical(), now + duration);
+    }
+
+    pub fn is_granted(&self, client: IpAddr) -> bool {
+        let grants = self.0.lock().unwrap();
+
+        grants
+            .get(&client.to_canonical())
+            .is_some_and(|&until| until > Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn knock(ports: &[u16]) -> Knock {
+        Knock {
+            ip: "0.0.0.0".parse().unwrap(),
+            ports: ports.to_vec(),
+            upstream: "10.0.0.1".parse().unwrap(),
+            window: Duration::from_secs(10),
+            grant: Duration::from_secs(300),
+            progress: Default::default(),
+        }
+    }
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 3));
+
+    #[test]
+    fn sequence_in_order() {
+        let k = knock(&[7000, 8000, 9000]);
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(k.hit(CLIENT, 9000));
+    }
+
+    #[test]
+    fn single_port() {
+        let k = knock(&[7000]);
+
+        assert!(k.hit(CLIENT, 7000));
+        assert!(k.hit(CLIENT, 7000));
+    }
+
+    #[test]
+    fn out_of_order_resets_progress() {
+        let k = knock(&[7000, 8000, 9000]);
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 9000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(!k.hit(CLIENT, 9000));
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(k.hit(CLIENT, 9000));
+    }
+
+    #[test]
+    fn first_port_starts_over() {
+        let k = knock(&[7000, 8000, 9000]);
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 9000));
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(k.hit(CLIENT, 9000));
+    }
+
+    #[test]
+    fn completing_resets_progress() {
+        let k = knock(&[7000, 8000]);
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(k.hit(CLIENT, 8000));
+        assert!(!k.hit(CLIENT, 8000));
+    }
+
+    #[test]
+    fn clients_knock_separately() {
+        let k = knock(&[7000, 8000, 9000]);
+
+        assert!(!k.hit(CLIENT, 7000));
+        assert!(!k.hit(OTHER, 8000));
+        assert!(!k.hit(CLIENT, 8000));
+        assert!(!k.hit(OTHER, 9000));
+        assert!(k.hit(CLIENT, 9000));
+    }
+
+    #[test]
+    fn grants() {
+        let grants = Grants::default();
+        assert!(!grants.is_granted(CLIENT));
+
+        grants.grant(CLIENT, Duration::from_secs(60));
+        assert!(grants.is_granted(CLIENT));
+        assert!(grants.is_granted("::ffff:192.168.0.2".parse().unwrap()));
+        assert!(!grants.is_granted(OTHER));
+    }
+}
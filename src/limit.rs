@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::num::NonZero;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Allow `burst` wakes at once, refilled evenly over `period`.
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    pub burst: NonZero<u32>,
+    pub period: Duration,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst.get().into(),
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let burst = f64::from(self.rate.burst.get());
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed / self.rate.period.as_secs_f64() * burst).min(burst);
+        self.last = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.rate.burst.get())
+    }
+
+    fn take(&mut self) -> bool {
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Token buckets limiting how often an upstream is woken up, both overall
+/// and per client address.
+#[derive(Debug)]
+pub struct WakeLimit {
+    upstream: Option<Mutex<TokenBucket>>,
+    client: Option<(Rate, Mutex<HashMap<IpAddr, TokenBucket>>)>,
+}
+
+impl WakeLimit {
+    pub fn new(upstream: Option<Rate>, client: Option<Rate>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            upstream: upstream.map(|x| Mutex::new(TokenBucket::new(x, now))),
+            client: client.map(|x| (x, Mutex::new(HashMap::new()))),
+        }
+    }
+
+    /// Take a token for waking up the upstream, or fail if the upstream has
+    /// been woken up too often.
+    pub fn take(&self) -> io::Result<()> {
+        let Some(bucket) = &self.upstream else {
+            return Ok(());
+        };
+
+        let mut bucket = bucket.lock().unwrap();
+        bucket.refill(Instant::now());
+
+        if !bucket.take() {
+            return Err(limited("upstream has been woken up too often"));
+        }
+
+        Ok(())
+    }
+
+    /// Take a token for `client` wanting to wake up the upstream, or fail if
+    /// it has done so too often.
+    pub fn take_client(&self, client: IpAddr) -> io::Result<()> {
+        let Some((rate, clients)) = &self.client else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+
+        let mut clients = clients.lock().unwrap();
+        clients.values_mut().for_each(|x| x.refill(now));
+        clients.retain(|_, x| !x.is_full());
+
+        let bucket = clients
+            .entry(client.to_canonical())
+            .or_insert_with(|| TokenBucket::new(*rate, now));
+
+        if !bucket.take() {
+            return Err(limited("client has woken up the upstream too often"));
+        }
+
+        Ok(())
+    }
+}
+
+fn limited(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::QuotaExceeded, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(burst: u32, period: u64) -> Rate {
+        Rate {
+            burst: NonZero::new(burst).unwrap(),
+            period: Duration::from_secs(period),
+        }
+    }
+
+    #[test]
+    fn bucket_starts_full() {
+        let mut bucket = TokenBucket::new(rate(3, 60), Instant::now());
+
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(!bucket.take());
+    }
+
+    #[test]
+    fn bucket_refills_evenly() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(rate(2, 10), now);
+
+        assert!(bucket.take());
+        assert!(bucket.take());
+
+        bucket.refill(now + Duration::from_secs(4));
+        assert!(!bucket.take());
+
+        bucket.refill(now + Duration::from_secs(5));
+        assert!(bucket.take());
+        assert!(!bucket.take());
+    }
+
+    #[test]
+    fn bucket_refills_up_to_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(rate(2, 10), now);
+
+        assert!(bucket.take());
+        bucket.refill(now + Duration::from_secs(1000));
+        assert!(bucket.is_full());
+
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(!bucket.take());
+    }
+
+    #[test]
+    fn clients_are_limited_separately() {
+        let limit = WakeLimit::new(None, Some(rate(1, 3600)));
+        let a = "10.0.0.1".parse().unwrap();
+        let b = "10.0.0.2".parse().unwrap();
+
+        assert!(limit.take_client(a).is_ok());
+        assert!(limit.take_client(a).is_err());
+        assert!(limit.take_client(b).is_ok());
+    }
+
+    #[test]
+    fn mapped_addresses_share_a_bucket() {
+        let limit = WakeLimit::new(None, Some(rate(1, 3600)));
+        let v4 = "10.0.0.1".parse().unwrap();
+        let mapped = "::ffff:10.0.0.1".parse().unwrap();
+
+        assert!(limit.take_client(v4).is_ok());
+        assert!(limit.take_client(mapped).is_err());
+    }
+
+    #[test]
+    fn upstream_is_limited() {
+        let limit = WakeLimit::new(Some(rate(1, 3600)), None);
+
+        assert!(limit.take().is_ok());
+        assert!(limit.take().is_err());
+    }
+
+    #[test]
+    fn no_limits() {
+        let limit = WakeLimit::new(None, None);
+
+        for _ in 0..100 {
+            assert!(limit.take().is_ok());
+            assert!(limit.take_client("10.0.0.1".parse().unwrap()).is_ok());
+        }
+    }
+}
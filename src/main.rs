@@ -1,29 +1,55 @@
 #[macro_use]
 extern crate tracing;
 
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use eyre::{Context, Result};
 use tracing::level_filters::LevelFilter;
 
 #[macro_use]
 mod display;
 
+mod access;
 mod app;
+mod backoff;
+mod client;
+mod depends;
+mod ether;
+mod flight;
 mod hardware_addr;
+mod heartbeat;
+mod idle;
+mod iface;
+mod knock;
+mod limit;
 mod mapping;
+mod monitor;
 mod parse;
+mod pool;
+mod power;
+mod preamble;
+mod proxy;
+mod retry;
+mod schedule;
+mod secureon;
 mod signal;
+mod transparent;
+mod tunnels;
+mod udp;
 mod upstream;
 mod util;
+mod wake_on;
+mod waker;
 
 use self::app::App;
 
 #[derive(Debug, Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct CliArgs {
-    #[clap(short, help = "Be verbose.")]
+    #[clap(short, global = true, help = "Be verbose.")]
     verbose: bool,
 
     #[clap(
@@ -32,6 +58,24 @@ struct CliArgs {
         default_value = "wolly.conf"
     )]
     config_path: PathBuf,
+
+    #[clap(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    #[clap(about = "Forward local connections to a listener that requires 'auth-key'.")]
+    Client {
+        #[clap(help = "Where to listen for local connections.")]
+        listen: SocketAddr,
+
+        #[clap(help = "The wolly listener to forward connections to.")]
+        server: String,
+
+        #[clap(long, env = "WOLLY_KEY", help = "The 'auth-key' of the listener.")]
+        key: String,
+    },
 }
 
 #[tokio::main]
@@ -60,6 +104,15 @@ async fn main() -> ExitCode {
 }
 
 async fn try_main(args: CliArgs) -> Result<()> {
+    if let Some(CliCommand::Client {
+        listen,
+        server,
+        key,
+    }) = args.command
+    {
+        return client::run(listen, server, key.as_str().into()).await;
+    }
+
     let app = read(&args.config_path)
         .await
         .with_context(|| format!("{}", display!(args.config_path)))?;
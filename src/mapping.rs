@@ -1,7 +1,14 @@
 use std::net::{IpAddr, SocketAddr};
-use std::ops::Range;
+use std::num::NonZero;
+use std::ops::{Range, RangeInclusive};
+use std::time::Duration;
 
+use super::access::Access;
+use super::preamble::Preamble;
+use super::proxy;
+use super::transparent::Interception;
 use super::upstream::ConnectOpts;
+use super::wake_on::WakeOn;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MappingKind {
@@ -23,20 +30,50 @@ pub enum MappingKind {
         to_port_range_start: u16,
         port_range_len: u16,
     },
+
+    Pool {
+        from_ip: IpAddr,
+        from_ports: RangeInclusive<u16>,
+        pool: String,
+        port: u16,
+    },
+
+    Udp {
+        from_ip: IpAddr,
+        from_ports: RangeInclusive<u16>,
+        to: SocketAddr,
+        session_timeout: Duration,
+        queue: NonZero<usize>,
+    },
+
+    /// Connections intercepted for any upstream.
+    Transparent {
+        from: SocketAddr,
+        mode: Interception,
+    },
 }
 
 impl MappingKind {
-    pub fn upstream(&self) -> IpAddr {
+    pub fn upstream(&self) -> Option<IpAddr> {
         match self {
-            Self::OneToOne { to, .. } => to.ip(),
-            Self::ManyToOne { to, .. } => to.ip(),
-            Self::ManyToMany { to_ip, .. } => *to_ip,
+            Self::OneToOne { to, .. } => Some(to.ip()),
+            Self::ManyToOne { to, .. } => Some(to.ip()),
+            Self::ManyToMany { to_ip, .. } => Some(*to_ip),
+            Self::Pool { .. } => None,
+            Self::Udp { to, .. } => Some(to.ip()),
+            Self::Transparent { .. } => None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Mapping {
     pub kind: MappingKind,
     pub opts: ConnectOpts,
+    pub wake_on: Option<WakeOn>,
+    pub access: Access,
+    pub preamble: Option<Preamble>,
+    pub fallback: Option<SocketAddr>,
+    pub send_proxy: Option<proxy::Version>,
+    pub accept_proxy: Option<proxy::AcceptProxy>,
 }
@@ -0,0 +1,98 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{MissedTickBehavior, interval, timeout};
+
+use crate::upstream::Upstream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeKind {
+    Tcp(u16),
+    Icmp,
+}
+
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub kind: ProbeKind,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Probe {
+    pub async fn check(&self, address: IpAddr) -> io::Result<()> {
+        let check = async {
+            match self.kind {
+                ProbeKind::Tcp(port) => tcp(address, port).await,
+                ProbeKind::Icmp => icmp(address).await,
+            }
+        };
+
+        timeout(self.timeout, check)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+}
+
+async fn tcp(address: IpAddr, port: u16) -> io::Result<()> {
+    match TcpStream::connect((address, port)).await {
+        Ok(_) => Ok(()),
+        // A refused connection still means something answered.
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+async fn icmp(address: IpAddr) -> io::Result<()> {
+    let (domain, protocol, echo_request) = match address {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, 8),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, 128),
+    };
+
+    // Unprivileged ICMP sockets, the kernel fills in the identifier and the
+    // checksum.
+    let s = Socket::new(domain, Type::DGRAM, Some(protocol))?;
+    s.set_nonblocking(true)?;
+    let s = UdpSocket::from_std(s.into())?;
+
+    s.connect((address, 0)).await?;
+    s.send(&[echo_request, 0, 0, 0, 0, 0, 0, 1]).await?;
+
+    let mut buf = [0u8; 64];
+    s.recv(&mut buf).await?;
+    Ok(())
+}
+
+impl Upstream {
+    pub async fn monitor(&self) {
+        let Some(probe) = &self.probe else {
+            return;
+        };
+
+        let mut ticks = interval(probe.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticks.tick().await;
+
+            let reachable = match probe.check(self.address).await {
+                Ok(()) => true,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    warn!(
+                        "not permitted to probe upstream, stopping monitor: {}",
+                        display!(e)
+                    );
+                    return;
+                }
+                Err(e) => {
+                    debug!("probe failed: {}", display!(e));
+                    false
+                }
+            };
+
+            self.power.observe(reachable);
+        }
+    }
+}
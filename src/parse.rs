@@ -1,21 +1,42 @@
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::iter::Peekable;
-use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr};
 use std::num::{NonZero, ParseFloatError, ParseIntError};
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
 use std::str::FromStr;
 use std::time::Duration;
 
 use miniarg::split_args::SplitArgs;
 use thiserror::Error;
 
+use crate::access::{Access, ParseCidrError};
+use crate::backoff::{Backoff, Strategy};
+use crate::depends::Dependency;
+use crate::flight::SingleFlight;
 use crate::hardware_addr;
+use crate::heartbeat::{Heartbeat, HeartbeatKind};
+use crate::iface::Interface;
+use crate::knock::Knock;
+use crate::limit::{Rate, WakeLimit};
+use crate::monitor::{Probe, ProbeKind};
+use crate::pool::{Balance, Pool};
+use crate::power::Power;
+use crate::preamble::Preamble;
+use crate::proxy;
+use crate::retry::{ParseRetryOnError, RetryOn};
+use crate::schedule::{ParseScheduleError, PreWake, QuietMode, Schedule, Window};
+use crate::secureon::{self, Password};
+use crate::transparent::Interception;
+use crate::tunnels::Tunnels;
+use crate::util::{UnescapeError, unescape};
+use crate::wake_on::WakeOn;
 
 use super::App;
 use super::mapping::{Mapping, MappingKind};
 use super::upstream::{ConnectOpts, Upstream};
+use super::waker::{self, Command, Http, HttpMethod, MagicPacket, WakeMode, Waker};
 
 type ParseStream<'a> = Peekable<SplitArgs<'a>>;
 
@@ -38,23 +59,293 @@ pub enum ParseUpstreamError {
 
     #[error("invalid broadcast address: {0}")]
     InvalidBroadcast(AddrParseError),
+
+    #[error("broadcast address {0} is not of the same family as the upstream address")]
+    MismatchedBroadcast(IpAddr),
+
+    #[error("IPv6 has no broadcast, {0} is not a multicast address")]
+    NotMulticast(Ipv6Addr),
+
+    #[error("expected 'magic', 'http' or 'command' for 'waker'")]
+    ExpectedWaker,
+
+    #[error("unknown waker '{0}'")]
+    UnknownWaker(String),
+
+    #[error("expected a URL for 'wake-url'")]
+    ExpectedWakeUrl,
+
+    #[error("invalid wake URL: {0}")]
+    InvalidWakeUrl(waker::ParseUrlError),
+
+    #[error("expected 'get' or 'post' for 'wake-method'")]
+    ExpectedWakeMethod,
+
+    #[error("unknown HTTP method '{0}'")]
+    UnknownWakeMethod(String),
+
+    #[error("expected a command for 'wake-cmd'")]
+    ExpectedWakeCmd,
+
+    #[error("expected a command for 'sleep-cmd'")]
+    ExpectedSleepCmd,
+
+    #[error("command is empty")]
+    EmptyCommand,
+
+    #[error("expected a delay for 'idle-sleep'")]
+    ExpectedIdleSleep,
+
+    #[error("invalid idle sleep delay: {0}")]
+    InvalidIdleSleep(ParseIntError),
+
+    #[error("'idle-sleep' requires 'sleep-cmd'")]
+    IdleSleepWithoutCmd,
+
+    #[error("expected a timeout for 'wake-timeout'")]
+    ExpectedWakeTimeout,
+
+    #[error("invalid wake timeout: {0}")]
+    InvalidWakeTimeout(ParseIntError),
+
+    #[error("expected an interface name or index")]
+    ExpectedInterface,
+
+    #[error("expected a source address for 'wake-src'")]
+    ExpectedWakeSource,
+
+    #[error("invalid wake source address: {0}")]
+    InvalidWakeSource(AddrParseError),
+
+    #[error("wake source address {0} is not of the same family as the upstream address")]
+    MismatchedWakeSource(IpAddr),
+
+    #[error("link-local multicast address {0} requires 'iface'")]
+    ExpectedScope(Ipv6Addr),
+
+    #[error("expected 'udp' or 'ether' for 'mode'")]
+    ExpectedMode,
+
+    #[error("unknown wake mode '{0}'")]
+    UnknownMode(String),
+
+    #[error("expected a SecureOn password")]
+    ExpectedPassword,
+
+    #[error("invalid SecureOn password: {0}")]
+    InvalidPassword(secureon::ParseError),
+
+    #[error("expected a port for 'wake-port'")]
+    ExpectedWakePort,
+
+    #[error("invalid wake port: {0}")]
+    InvalidWakePort(ParseIntError),
+
+    #[error("expected a number for 'wake-repeat'")]
+    ExpectedWakeRepeat,
+
+    #[error("invalid wake repeat count: {0}")]
+    InvalidWakeRepeat(ParseIntError),
+
+    #[error("expected a delay for 'wake-interval'")]
+    ExpectedWakeInterval,
+
+    #[error("invalid wake interval: {0}")]
+    InvalidWakeInterval(ParseIntError),
+
+    #[error("expected a delay for 'wake-resend'")]
+    ExpectedWakeResend,
+
+    #[error("invalid wake resend delay: {0}")]
+    InvalidWakeResend(ParseIntError),
+
+    #[error("expected 'tcp' or 'wake' for 'heartbeat'")]
+    ExpectedHeartbeat,
+
+    #[error("unknown heartbeat type '{0}'")]
+    UnknownHeartbeat(String),
+
+    #[error("expected a port for 'heartbeat tcp'")]
+    ExpectedHeartbeatPort,
+
+    #[error("invalid heartbeat port: {0}")]
+    InvalidHeartbeatPort(ParseIntError),
+
+    #[error("expected an interval for 'heartbeat-interval'")]
+    ExpectedHeartbeatInterval,
+
+    #[error("invalid heartbeat interval: {0}")]
+    InvalidHeartbeatInterval(ParseIntError),
+
+    #[error("expected 'tcp' or 'icmp' for 'probe'")]
+    ExpectedProbe,
+
+    #[error("unknown probe type '{0}'")]
+    UnknownProbe(String),
+
+    #[error("expected a port for 'probe tcp'")]
+    ExpectedProbePort,
+
+    #[error("invalid probe port: {0}")]
+    InvalidProbePort(ParseIntError),
+
+    #[error("expected an interval for 'probe-interval'")]
+    ExpectedProbeInterval,
+
+    #[error("invalid probe interval: {0}")]
+    InvalidProbeInterval(ParseIntError),
+
+    #[error("expected a timeout for 'probe-timeout'")]
+    ExpectedProbeTimeout,
+
+    #[error("invalid probe timeout: {0}")]
+    InvalidProbeTimeout(ParseIntError),
+
+    #[error("expected a network for '{0}'")]
+    ExpectedNetwork(&'static str),
+
+    #[error("invalid network: {0}")]
+    InvalidNetwork(ParseCidrError),
+
+    #[error("expected a count and a period for '{0}'")]
+    ExpectedWakeLimit(&'static str),
+
+    #[error("invalid wake limit: {0}")]
+    InvalidWakeLimit(ParseIntError),
+
+    #[error("expected days and a range of times for 'quiet-hours'")]
+    ExpectedQuietHours,
+
+    #[error("invalid quiet hours: {0}")]
+    InvalidQuietHours(ParseScheduleError),
+
+    #[error("expected 'refuse' or 'awake-only' for 'quiet-mode'")]
+    ExpectedQuietMode,
+
+    #[error("unknown quiet mode '{0}'")]
+    UnknownQuietMode(String),
+
+    #[error("expected days and a time for 'pre-wake'")]
+    ExpectedPreWake,
+
+    #[error("invalid pre-wake time: {0}")]
+    InvalidPreWake(ParseScheduleError),
+
+    #[error("'pre-wake' requires 'probe'")]
+    PreWakeWithoutProbe,
+
+    #[error("expected a timeout for 'pre-wake-timeout'")]
+    ExpectedPreWakeTimeout,
+
+    #[error("invalid pre-wake timeout: {0}")]
+    InvalidPreWakeTimeout(ParseIntError),
+
+    #[error("expected the address and port of a dependency")]
+    ExpectedDependency,
+
+    #[error("invalid dependency: {0}")]
+    InvalidDependency(AddrParseError),
 }
 
 impl Upstream {
-    fn parse(stream: &mut ParseStream<'_>) -> Result<Self, ParseUpstreamError> {
+    fn parse(
+        stream: &mut ParseStream<'_>,
+        defaults: &Defaults,
+    ) -> Result<Self, ParseUpstreamError> {
         use ParseUpstreamError::*;
 
-        let address = stream
+        let address: IpAddr = stream
             .next()
             .ok_or(ExpectedAddress)?
             .parse()
             .map_err(InvalidAddress)?;
 
+        let mut waker = WakerKind::Magic;
+        let mut wake_url = None;
+        let mut wake_method = HttpMethod::Get;
+        let mut wake_cmd = None;
+        let mut sleep_cmd = None;
+        let mut idle_sleep = None;
+        let mut wake_timeout = Duration::from_secs(10);
         let mut mac = None;
-        let mut broadcast = None;
+        let mut broadcast = Vec::new();
+        let mut iface = None;
+        let mut wake_src: Option<IpAddr> = None;
+        let mut mode = WakeMode::Udp;
+        let mut password = None;
+        let mut wake_port = 9;
+        let mut wake_repeat = NonZero::new(1).expect("1 is not 0");
+        let mut wake_interval = Duration::from_secs(1);
+        let mut wake_resend = None;
+        let mut heartbeat = None;
+        let mut heartbeat_interval = Duration::from_secs(60);
+        let mut probe = None;
+        let mut probe_interval = Duration::from_secs(30);
+        let mut probe_timeout = Duration::from_secs(1);
+        let mut access = Access::default();
+        let mut wake_limit = None;
+        let mut client_wake_limit = None;
+        let mut quiet = Vec::new();
+        let mut quiet_mode = QuietMode::AwakeOnly;
+        let mut pre_wake = Vec::new();
+        let mut pre_wake_timeout = Duration::from_secs(300);
+        let mut requires = Vec::new();
 
         while let Some(x) = stream.next() {
             match x {
+                "waker" => {
+                    waker = match stream.next().ok_or(ExpectedWaker)? {
+                        "magic" => WakerKind::Magic,
+                        "http" => WakerKind::Http,
+                        "command" => WakerKind::Command,
+                        x => return Err(UnknownWaker(x.to_owned())),
+                    };
+                }
+
+                "wake-url" => {
+                    wake_url = Some(
+                        stream
+                            .next()
+                            .ok_or(ExpectedWakeUrl)?
+                            .parse()
+                            .map_err(InvalidWakeUrl)?,
+                    );
+                }
+
+                "wake-method" => {
+                    wake_method = match stream.next().ok_or(ExpectedWakeMethod)? {
+                        "get" | "GET" => HttpMethod::Get,
+                        "post" | "POST" => HttpMethod::Post,
+                        x => return Err(UnknownWakeMethod(x.to_owned())),
+                    };
+                }
+
+                "wake-cmd" => {
+                    wake_cmd = Some(stream.next().ok_or(ExpectedWakeCmd)?);
+                }
+
+                "sleep-cmd" => {
+                    sleep_cmd = Some(stream.next().ok_or(ExpectedSleepCmd)?);
+                }
+
+                "idle-sleep" => {
+                    idle_sleep = stream
+                        .next()
+                        .ok_or(ExpectedIdleSleep)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidIdleSleep)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "wake-timeout" => {
+                    wake_timeout = stream
+                        .next()
+                        .ok_or(ExpectedWakeTimeout)?
+                        .parse()
+                        .map_err(InvalidWakeTimeout)
+                        .map(Duration::from_secs)?;
+                }
+
                 "mac" => {
                     mac = Some(
                         stream
@@ -66,7 +357,7 @@ impl Upstream {
                 }
 
                 "brd" => {
-                    broadcast = Some(
+                    broadcast.push(
                         stream
                             .next()
                             .ok_or(ExpectedBroadcast)?
@@ -75,29 +366,475 @@ impl Upstream {
                     );
                 }
 
+                "wake-iface" | "iface" => {
+                    iface = Some(parse_iface(stream.next().ok_or(ExpectedInterface)?));
+                }
+
+                "wake-src" => {
+                    wake_src = Some(
+                        stream
+                            .next()
+                            .ok_or(ExpectedWakeSource)?
+                            .parse()
+                            .map_err(InvalidWakeSource)?,
+                    );
+                }
+
+                "mode" => {
+                    mode = match stream.next().ok_or(ExpectedMode)? {
+                        "udp" => WakeMode::Udp,
+                        "ether" => WakeMode::Ether,
+                        x => return Err(UnknownMode(x.to_owned())),
+                    };
+                }
+
+                "password" => {
+                    password = Some(
+                        stream
+                            .next()
+                            .ok_or(ExpectedPassword)?
+                            .parse::<Password>()
+                            .map_err(InvalidPassword)?,
+                    );
+                }
+
+                "wake-port" => {
+                    wake_port = stream
+                        .next()
+                        .ok_or(ExpectedWakePort)?
+                        .parse()
+                        .map_err(InvalidWakePort)?;
+                }
+
+                "wake-repeat" => {
+                    wake_repeat = stream
+                        .next()
+                        .ok_or(ExpectedWakeRepeat)?
+                        .parse()
+                        .map_err(InvalidWakeRepeat)?;
+                }
+
+                "wake-interval" => {
+                    wake_interval = stream
+                        .next()
+                        .ok_or(ExpectedWakeInterval)?
+                        .parse()
+                        .map_err(InvalidWakeInterval)
+                        .map(Duration::from_secs)?;
+                }
+
+                "wake-resend" => {
+                    wake_resend = stream
+                        .next()
+                        .ok_or(ExpectedWakeResend)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidWakeResend)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "heartbeat" => {
+                    heartbeat = Some(match stream.next().ok_or(ExpectedHeartbeat)? {
+                        "tcp" => HeartbeatKind::Tcp(
+                            stream
+                                .next()
+                                .ok_or(ExpectedHeartbeatPort)?
+                                .parse()
+                                .map_err(InvalidHeartbeatPort)?,
+                        ),
+                        "wake" => HeartbeatKind::Wake,
+                        x => return Err(UnknownHeartbeat(x.to_owned())),
+                    });
+                }
+
+                "heartbeat-interval" => {
+                    heartbeat_interval = stream
+                        .next()
+                        .ok_or(ExpectedHeartbeatInterval)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidHeartbeatInterval)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                "probe" => {
+                    probe = Some(match stream.next().ok_or(ExpectedProbe)? {
+                        "tcp" => ProbeKind::Tcp(
+                            stream
+                                .next()
+                                .ok_or(ExpectedProbePort)?
+                                .parse()
+                                .map_err(InvalidProbePort)?,
+                        ),
+                        "icmp" => ProbeKind::Icmp,
+                        x => return Err(UnknownProbe(x.to_owned())),
+                    });
+                }
+
+                "probe-interval" => {
+                    probe_interval = stream
+                        .next()
+                        .ok_or(ExpectedProbeInterval)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidProbeInterval)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                "probe-timeout" => {
+                    probe_timeout = stream
+                        .next()
+                        .ok_or(ExpectedProbeTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidProbeTimeout)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                "allow" => {
+                    access.allow.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("allow"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "deny" => {
+                    access.deny.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("deny"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "may-wake" => {
+                    access.wake.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("may-wake"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "wake-limit" => {
+                    wake_limit = Some(parse_rate(stream, "wake-limit")?);
+                }
+
+                "client-wake-limit" => {
+                    client_wake_limit = Some(parse_rate(stream, "client-wake-limit")?);
+                }
+
+                "quiet-hours" => {
+                    let days = stream.next().ok_or(ExpectedQuietHours)?;
+                    let range = stream.next().ok_or(ExpectedQuietHours)?;
+
+                    quiet.push(Window::parse(days, range).map_err(InvalidQuietHours)?);
+                }
+
+                "quiet-mode" => {
+                    quiet_mode = match stream.next().ok_or(ExpectedQuietMode)? {
+                        "refuse" => QuietMode::Refuse,
+                        "awake-only" => QuietMode::AwakeOnly,
+                        x => return Err(UnknownQuietMode(x.to_owned())),
+                    };
+                }
+
+                "requires" | "after" => {
+                    let addr: SocketAddr = stream
+                        .next()
+                        .ok_or(ExpectedDependency)?
+                        .parse()
+                        .map_err(InvalidDependency)?;
+
+                    requires.push(Dependency {
+                        address: addr.ip(),
+                        port: addr.port(),
+                        required: x == "requires",
+                    });
+                }
+
+                "pre-wake" => {
+                    pre_wake.push(PreWake {
+                        days: stream
+                            .next()
+                            .ok_or(ExpectedPreWake)?
+                            .parse()
+                            .map_err(InvalidPreWake)?,
+                        at: stream
+                            .next()
+                            .ok_or(ExpectedPreWake)?
+                            .parse()
+                            .map_err(InvalidPreWake)?,
+                    });
+                }
+
+                "pre-wake-timeout" => {
+                    pre_wake_timeout = stream
+                        .next()
+                        .ok_or(ExpectedPreWakeTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidPreWakeTimeout)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
                 _ => {
                     warn!("ignoring unknown property '{x}'");
                 }
             }
         }
 
-        let mac = mac.ok_or(ExpectedMac)?;
-        let broadcast = broadcast.ok_or(ExpectedBroadcast)?;
+        let sleep_cmd = match sleep_cmd {
+            Some(x) => Some(Command::new(x, address, mac).ok_or(EmptyCommand)?),
+            None => None,
+        };
+
+        if idle_sleep.is_some() && sleep_cmd.is_none() {
+            return Err(IdleSleepWithoutCmd);
+        }
+
+        // A scheduled wake connects to nothing, so only the probe can tell
+        // whether it worked.
+        if !pre_wake.is_empty() && probe.is_none() {
+            return Err(PreWakeWithoutProbe);
+        }
+
+        let waker: Box<dyn Waker> = match waker {
+            WakerKind::Magic => {
+                let mac = mac.ok_or(ExpectedMac)?;
+
+                let iface = iface.unwrap_or_else(|| defaults.wake_iface.clone());
+
+                let source = match wake_src {
+                    Some(x) if x.is_ipv4() != address.is_ipv4() => {
+                        return Err(MismatchedWakeSource(x));
+                    }
+                    Some(x) => Some(x),
+                    None => defaults
+                        .wake_src
+                        .iter()
+                        .find(|x| x.is_ipv4() == address.is_ipv4())
+                        .copied(),
+                };
+
+                match mode {
+                    WakeMode::Udp => {
+                        if broadcast.is_empty() {
+                            match address {
+                                IpAddr::V6(_) => broadcast.push(IpAddr::V6(ALL_NODES)),
+                                IpAddr::V4(_) => return Err(ExpectedBroadcast),
+                            }
+                        }
+
+                        for &x in &broadcast {
+                            validate_broadcast(address, x, iface.as_ref())?;
+                        }
+                    }
+
+                    WakeMode::Ether if iface.is_none() => return Err(ExpectedInterface),
+                    WakeMode::Ether => {}
+                }
+
+                Box::new(MagicPacket {
+                    mac,
+                    address,
+                    broadcast,
+                    iface,
+                    source,
+                    mode,
+                    password,
+                    port: wake_port,
+                    repeat: wake_repeat,
+                    interval: wake_interval,
+                    resend: wake_resend,
+                })
+            }
+
+            WakerKind::Http => Box::new(Http {
+                url: wake_url.ok_or(ExpectedWakeUrl)?,
+                method: wake_method,
+            }),
+
+            WakerKind::Command => {
+                let x = wake_cmd.ok_or(ExpectedWakeCmd)?;
+                Box::new(Command::new(x, address, mac).ok_or(EmptyCommand)?)
+            }
+        };
+
+        let heartbeat = heartbeat.map(|kind| Heartbeat {
+            kind,
+            interval: heartbeat_interval,
+        });
+
+        let probe = probe.map(|kind| Probe {
+            kind,
+            interval: probe_interval,
+            timeout: probe_timeout,
+        });
 
         Ok(Self {
             address,
-            mac,
-            broadcast,
+            waker,
+            wake_timeout,
+            sleep_cmd,
+            idle_sleep,
+            heartbeat,
+            probe,
+            access,
+            wake_limit: WakeLimit::new(wake_limit, client_wake_limit),
+            grants: None,
+            schedule: Schedule {
+                quiet,
+                quiet_mode,
+                pre_wake,
+                pre_wake_timeout,
+            },
+            requires,
+            power: Power::default(),
+            flight: SingleFlight::default(),
+            tunnels: Tunnels::default(),
         })
     }
 }
 
+// Syntax: <count> <seconds>
+fn parse_rate(
+    stream: &mut ParseStream<'_>,
+    name: &'static str,
+) -> Result<Rate, ParseUpstreamError> {
+    use ParseUpstreamError::*;
+
+    let burst = stream
+        .next()
+        .ok_or(ExpectedWakeLimit(name))?
+        .parse()
+        .map_err(InvalidWakeLimit)?;
+
+    let period = stream
+        .next()
+        .ok_or(ExpectedWakeLimit(name))?
+        .parse::<NonZero<u64>>()
+        .map_err(InvalidWakeLimit)?;
+
+    Ok(Rate {
+        burst,
+        period: Duration::from_secs(period.get()),
+    })
+}
+
+// Syntax: <name> | <index> | none
+fn parse_iface(s: &str) -> Option<Interface> {
+    if s == "none" {
+        return None;
+    }
+
+    let Ok(x) = s.parse();
+    Some(x)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WakerKind {
+    Magic,
+    Http,
+    Command,
+}
+
+#[derive(Debug, Default)]
+struct Defaults {
+    wake_iface: Option<Interface>,
+    wake_src: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseDefaultsError {
+    #[error("expected an interface name or index")]
+    ExpectedInterface,
+
+    #[error("expected a source address for 'wake-src'")]
+    ExpectedWakeSource,
+
+    #[error("invalid wake source address: {0}")]
+    InvalidWakeSource(AddrParseError),
+}
+
+impl Defaults {
+    fn parse(&mut self, stream: &mut ParseStream<'_>) -> Result<(), ParseDefaultsError> {
+        use ParseDefaultsError::*;
+
+        while let Some(x) = stream.next() {
+            match x {
+                "wake-iface" | "iface" => {
+                    self.wake_iface = parse_iface(stream.next().ok_or(ExpectedInterface)?);
+                }
+
+                "wake-src" => {
+                    let x: IpAddr = stream
+                        .next()
+                        .ok_or(ExpectedWakeSource)?
+                        .parse()
+                        .map_err(InvalidWakeSource)?;
+
+                    // One source address per address family.
+                    self.wake_src.retain(|y| y.is_ipv4() != x.is_ipv4());
+                    self.wake_src.push(x);
+                }
+
+                _ => {
+                    warn!("ignoring unknown property '{x}'");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+fn validate_broadcast(
+    address: IpAddr,
+    broadcast: IpAddr,
+    iface: Option<&Interface>,
+) -> Result<(), ParseUpstreamError> {
+    use ParseUpstreamError::*;
+
+    match (address, broadcast) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => Ok(()),
+
+        (IpAddr::V6(_), IpAddr::V6(x)) => {
+            if !x.is_multicast() {
+                return Err(NotMulticast(x));
+            }
+
+            // Interface-local (1) and link-local (2) scopes.
+            let scope = x.segments()[0] & 0x000f;
+            if matches!(scope, 1 | 2) && iface.is_none() {
+                return Err(ExpectedScope(x));
+            }
+
+            Ok(())
+        }
+
+        (_, x) => Err(MismatchedBroadcast(x)),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Port {
     Single(u16),
     Range(Range<u16>),
 }
 
+impl Port {
+    /// Every port, as a range that can also end at port 65535.
+    fn ports(self) -> RangeInclusive<u16> {
+        match self {
+            Port::Single(x) => x..=x,
+            Port::Range(x) => x.start..=x.end - 1,
+        }
+    }
+}
+
 impl fmt::Display for Port {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -137,11 +874,17 @@ impl FromStr for Address {
     type Err = ParseAddressError;
 
     // Syntax: <ip>:<port> | <ip>:<port>-<port>
+    //
+    // IPv6 addresses may be enclosed in brackets: [<ip>]:<port>
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use ParseAddressError::*;
 
-        let (ip, port) = s.split_once(':').ok_or(MissingSeparator)?;
+        let (ip, port) = s.rsplit_once(':').ok_or(MissingSeparator)?;
 
+        let ip = ip
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(ip);
         let ip = ip.parse().map_err(InvalidAddress)?;
 
         let port = match port.split_once('-') {
@@ -184,6 +927,12 @@ pub enum ParseMappingError {
     #[error("invalid 'to' address: {0}")]
     InvalidToAddress(ParseAddressError),
 
+    #[error("expected '<name>:<port>' of a pool")]
+    ExpectedPool,
+
+    #[error("invalid pool port: {0}")]
+    InvalidPoolPort(ParseIntError),
+
     #[error("expected a delay for 'wait-for'")]
     ExpectedWaitFor,
 
@@ -208,19 +957,159 @@ pub enum ParseMappingError {
     #[error("invalid retry factor: {0}")]
     InvalidRetryFactor(ParseFloatError),
 
-    #[error("'from' and 'to' ranges do not match in size")]
-    InvalidPortRanges,
+    #[error("'retry-factor' must be a positive number")]
+    RetryFactorOutOfRange,
 
-    #[error("cannot map a single port to a range of ports")]
-    InvalidMappingType,
-}
+    #[error("expected 'constant', 'linear', 'exponential' or 'decorrelated-jitter' for 'backoff'")]
+    ExpectedBackoff,
 
-impl Mapping {
-    fn parse(stream: &mut ParseStream<'_>) -> Result<Self, ParseMappingError> {
-        use ParseMappingError::*;
-        use Port::{Range, Single};
+    #[error("unknown backoff strategy '{0}'")]
+    UnknownBackoff(String),
 
-        let from: Address = stream
+    #[error("expected a delay for 'max-retry-delay'")]
+    ExpectedMaxRetryDelay,
+
+    #[error("invalid max retry delay: {0}")]
+    InvalidMaxRetryDelay(ParseIntError),
+
+    #[error("'max-retry-delay' is shorter than 'retry-delay'")]
+    MaxRetryDelayTooShort,
+
+    #[error("expected a number of seconds for 'deadline'")]
+    ExpectedDeadline,
+
+    #[error("invalid deadline: {0}")]
+    InvalidDeadline(ParseIntError),
+
+    #[error("expected a timeout for 'connect-timeout'")]
+    ExpectedConnectTimeout,
+
+    #[error("invalid connect timeout: {0}")]
+    InvalidConnectTimeout(ParseIntError),
+
+    #[error("expected conditions separated by ',' for 'retry-on'")]
+    ExpectedRetryOn,
+
+    #[error("invalid 'retry-on': {0}")]
+    InvalidRetryOn(ParseRetryOnError),
+
+    #[error("expected a number for 'wake-on-bytes'")]
+    ExpectedWakeOnBytes,
+
+    #[error("invalid byte count: {0}")]
+    InvalidWakeOnBytes(ParseIntError),
+
+    #[error("expected a prefix for 'wake-on-prefix'")]
+    ExpectedWakeOnPrefix,
+
+    #[error("invalid prefix: {0}")]
+    InvalidWakeOnPrefix(UnescapeError),
+
+    #[error("expected a timeout for 'wake-on-timeout'")]
+    ExpectedWakeOnTimeout,
+
+    #[error("invalid timeout: {0}")]
+    InvalidWakeOnTimeout(ParseIntError),
+
+    #[error("expected a network for '{0}'")]
+    ExpectedNetwork(&'static str),
+
+    #[error("invalid network: {0}")]
+    InvalidNetwork(ParseCidrError),
+
+    #[error("expected a key for 'auth-key'")]
+    ExpectedAuthKey,
+
+    #[error("expected a number of seconds for 'auth-skew'")]
+    ExpectedAuthSkew,
+
+    #[error("invalid clock skew: {0}")]
+    InvalidAuthSkew(ParseIntError),
+
+    #[error("expected an address for 'fallback'")]
+    ExpectedFallback,
+
+    #[error("invalid fallback address: {0}")]
+    InvalidFallback(AddrParseError),
+
+    #[error("'auth-skew' requires 'auth-key'")]
+    AuthSkewWithoutKey,
+
+    #[error("expected a timeout for 'auth-timeout'")]
+    ExpectedAuthTimeout,
+
+    #[error("invalid authentication timeout: {0}")]
+    InvalidAuthTimeout(ParseIntError),
+
+    #[error("'auth-timeout' requires 'auth-key'")]
+    AuthTimeoutWithoutKey,
+
+    #[error("expected a timeout for 'accept-proxy-timeout'")]
+    ExpectedAcceptProxyTimeout,
+
+    #[error("invalid PROXY header timeout: {0}")]
+    InvalidAcceptProxyTimeout(ParseIntError),
+
+    #[error("'accept-proxy-timeout' requires 'accept-proxy'")]
+    AcceptProxyTimeoutWithoutAcceptProxy,
+
+    #[error("'from' and 'to' ranges do not match in size")]
+    InvalidPortRanges,
+
+    #[error("cannot map a single port to a range of ports")]
+    InvalidMappingType,
+
+    #[error("expected a timeout for 'session-timeout'")]
+    ExpectedSessionTimeout,
+
+    #[error("invalid session timeout: {0}")]
+    InvalidSessionTimeout(ParseIntError),
+
+    #[error("expected a number of datagrams for 'queue'")]
+    ExpectedQueue,
+
+    #[error("invalid queue size: {0}")]
+    InvalidQueue(ParseIntError),
+
+    #[error("'{0}' only applies to 'forward udp'")]
+    UdpOnly(&'static str),
+
+    #[error("'{0}' cannot be used with 'forward udp'")]
+    NotForUdp(&'static str),
+
+    #[error("'forward udp' cannot map to a range of ports")]
+    UdpPortRange,
+
+    #[error("expected 'v1' or 'v2' for 'send-proxy'")]
+    ExpectedSendProxy,
+
+    #[error("unknown PROXY protocol version '{0}'")]
+    UnknownSendProxy(String),
+
+    #[error("expected 'redirect' or 'tproxy' after 'original'")]
+    ExpectedInterception,
+
+    #[error("unknown interception mode '{0}'")]
+    UnknownInterception(String),
+
+    #[error("intercepted connections must be received on a single port")]
+    TransparentPortRange,
+}
+
+enum Target {
+    Address(Address),
+    Pool(String, u16),
+    Original(Interception),
+}
+
+impl Mapping {
+    fn parse(stream: &mut ParseStream<'_>) -> Result<Self, ParseMappingError> {
+        use ParseMappingError::*;
+        use Port::{Range, Single};
+
+        let udp = stream.next_if_eq(&"udp").is_some();
+
+        let from: Address = stream
             .next()
             .ok_or(ExpectedFromAddress)?
             .parse()
@@ -233,16 +1122,48 @@ impl Mapping {
             }
         }
 
-        let to: Address = stream
-            .next()
-            .ok_or(ExpectedToAddress)?
-            .parse()
-            .map_err(InvalidToAddress)?;
+        let to = match stream.next().ok_or(ExpectedToAddress)? {
+            "pool" => {
+                let (pool, port) = stream
+                    .next()
+                    .ok_or(ExpectedPool)?
+                    .rsplit_once(':')
+                    .ok_or(ExpectedPool)?;
+
+                Target::Pool(pool.to_owned(), port.parse().map_err(InvalidPoolPort)?)
+            }
+
+            "original" => match stream.next().ok_or(ExpectedInterception)? {
+                "redirect" => Target::Original(Interception::Redirect),
+                "tproxy" => Target::Original(Interception::Tproxy),
+                x => return Err(UnknownInterception(x.to_owned())),
+            },
+
+            x => Target::Address(x.parse().map_err(InvalidToAddress)?),
+        };
 
         let mut wait_for = Duration::from_secs(0);
         let mut max_attempts = 5;
         let mut retry_delay = Duration::from_secs(1);
-        let mut retry_factor = 2.0;
+        let mut retry_factor: f64 = 2.0;
+        let mut backoff = Strategy::Exponential;
+        let mut max_retry_delay = None;
+        let mut deadline = None;
+        let mut connect_timeout = Duration::from_secs(10);
+        let mut retry_on = RetryOn::ALL.to_vec();
+        let mut wake_on_bytes = 0;
+        let mut wake_on_prefix = Vec::new();
+        let mut wake_on_timeout = Duration::from_secs(10);
+        let mut access = Access::default();
+        let mut auth_key = None;
+        let mut auth_skew = None;
+        let mut auth_timeout = None;
+        let mut fallback = None;
+        let mut session_timeout = None;
+        let mut queue = None;
+        let mut send_proxy = None;
+        let mut accept_proxy = None;
+        let mut accept_proxy_timeout = None;
 
         while let Some(x) = stream.next() {
             match x {
@@ -280,6 +1201,181 @@ impl Mapping {
                         .map_err(InvalidRetryFactor)?
                 }
 
+                "backoff" => {
+                    backoff = match stream.next().ok_or(ExpectedBackoff)? {
+                        "constant" => Strategy::Constant,
+                        "linear" => Strategy::Linear,
+                        "exponential" => Strategy::Exponential,
+                        "decorrelated-jitter" => Strategy::DecorrelatedJitter,
+                        x => return Err(UnknownBackoff(x.to_owned())),
+                    };
+                }
+
+                "max-retry-delay" => {
+                    max_retry_delay = stream
+                        .next()
+                        .ok_or(ExpectedMaxRetryDelay)?
+                        .parse()
+                        .map_err(InvalidMaxRetryDelay)
+                        .map(|x| Some(Duration::from_secs(x)))?;
+                }
+
+                "connect-timeout" => {
+                    connect_timeout = stream
+                        .next()
+                        .ok_or(ExpectedConnectTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidConnectTimeout)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                "retry-on" => {
+                    retry_on = stream
+                        .next()
+                        .ok_or(ExpectedRetryOn)?
+                        .split(',')
+                        .map(|x| x.parse().map_err(InvalidRetryOn))
+                        .collect::<Result<_, _>>()?;
+                }
+
+                "accept-proxy" => {
+                    accept_proxy = stream
+                        .next()
+                        .ok_or(ExpectedNetwork("accept-proxy"))?
+                        .split(',')
+                        .map(|x| x.parse().map_err(InvalidNetwork))
+                        .collect::<Result<_, _>>()
+                        .map(Some)?;
+                }
+
+                "accept-proxy-timeout" => {
+                    accept_proxy_timeout = stream
+                        .next()
+                        .ok_or(ExpectedAcceptProxyTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidAcceptProxyTimeout)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "send-proxy" => {
+                    send_proxy = match stream.next().ok_or(ExpectedSendProxy)? {
+                        "v1" => Some(proxy::Version::V1),
+                        "v2" => Some(proxy::Version::V2),
+                        x => return Err(UnknownSendProxy(x.to_owned())),
+                    };
+                }
+
+                "session-timeout" => {
+                    session_timeout = stream
+                        .next()
+                        .ok_or(ExpectedSessionTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidSessionTimeout)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "queue" => {
+                    queue = stream
+                        .next()
+                        .ok_or(ExpectedQueue)?
+                        .parse()
+                        .map_err(InvalidQueue)
+                        .map(Some)?;
+                }
+
+                "deadline" => {
+                    deadline = stream
+                        .next()
+                        .ok_or(ExpectedDeadline)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidDeadline)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "wake-on-bytes" => {
+                    wake_on_bytes = stream
+                        .next()
+                        .ok_or(ExpectedWakeOnBytes)?
+                        .parse()
+                        .map_err(InvalidWakeOnBytes)?
+                }
+
+                "wake-on-prefix" => {
+                    wake_on_prefix = unescape(stream.next().ok_or(ExpectedWakeOnPrefix)?)
+                        .map_err(InvalidWakeOnPrefix)?
+                }
+
+                "wake-on-timeout" => {
+                    wake_on_timeout = stream
+                        .next()
+                        .ok_or(ExpectedWakeOnTimeout)?
+                        .parse()
+                        .map_err(InvalidWakeOnTimeout)
+                        .map(Duration::from_secs)?
+                }
+
+                "allow" => {
+                    access.allow.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("allow"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "deny" => {
+                    access.deny.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("deny"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "may-wake" => {
+                    access.wake.push(
+                        stream
+                            .next()
+                            .ok_or(ExpectedNetwork("may-wake"))?
+                            .parse()
+                            .map_err(InvalidNetwork)?,
+                    );
+                }
+
+                "auth-key" => {
+                    auth_key = Some(stream.next().ok_or(ExpectedAuthKey)?.into());
+                }
+
+                "auth-timeout" => {
+                    auth_timeout = stream
+                        .next()
+                        .ok_or(ExpectedAuthTimeout)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidAuthTimeout)
+                        .map(|x| Some(Duration::from_secs(x.get())))?;
+                }
+
+                "fallback" => {
+                    fallback = Some(
+                        stream
+                            .next()
+                            .ok_or(ExpectedFallback)?
+                            .parse()
+                            .map_err(InvalidFallback)?,
+                    );
+                }
+
+                "auth-skew" => {
+                    auth_skew = stream
+                        .next()
+                        .ok_or(ExpectedAuthSkew)?
+                        .parse()
+                        .map_err(InvalidAuthSkew)
+                        .map(|x| Some(Duration::from_secs(x)))?;
+                }
+
                 _ => {
                     warn!("ignoring unknown property '{x}'");
                 }
@@ -297,64 +1393,379 @@ impl Mapping {
             }
         };
 
+        if !(retry_factor > 0.0 && retry_factor.is_finite()) {
+            return Err(RetryFactorOutOfRange);
+        }
+
+        if max_retry_delay.is_some_and(|x| x < retry_delay) {
+            return Err(MaxRetryDelayTooShort);
+        }
+
         let opts = ConnectOpts {
             wait_for,
             max_attempts,
-            retry_delay,
-            retry_factor,
+            backoff: Backoff {
+                strategy: backoff,
+                delay: retry_delay,
+                factor: retry_factor,
+                max_delay: max_retry_delay,
+            },
+            deadline,
+            connect_timeout,
+            retry_on,
         };
 
-        let kind = match (from.port.clone(), to.port) {
-            (Single(from_port), Single(to_port)) => MappingKind::OneToOne {
-                from: SocketAddr::new(from.ip, from_port),
-                to: SocketAddr::new(to.ip, to_port),
+        if udp {
+            let unsupported = [
+                ("wake-on-bytes", wake_on_bytes != 0),
+                ("wake-on-prefix", !wake_on_prefix.is_empty()),
+                ("auth-key", auth_key.is_some()),
+                ("fallback", fallback.is_some()),
+                ("send-proxy", send_proxy.is_some()),
+                ("accept-proxy", accept_proxy.is_some()),
+                ("accept-proxy-timeout", accept_proxy_timeout.is_some()),
+            ];
+
+            if let Some((option, _)) = unsupported.into_iter().find(|(_, x)| *x) {
+                return Err(NotForUdp(option));
+            }
+        } else if session_timeout.is_some() {
+            return Err(UdpOnly("session-timeout"));
+        } else if queue.is_some() {
+            return Err(UdpOnly("queue"));
+        }
+
+        let kind = match to {
+            Target::Pool(..) if udp => return Err(NotForUdp("pool")),
+            Target::Original(_) if udp => return Err(NotForUdp("original")),
+            Target::Address(to) if udp => MappingKind::Udp {
+                from_ip: from.ip,
+                from_ports: from.port.ports(),
+                to: match to.port {
+                    Single(x) => SocketAddr::new(to.ip, x),
+                    Range(_) => return Err(UdpPortRange),
+                },
+                session_timeout: session_timeout.unwrap_or(Duration::from_secs(60)),
+                queue: queue.unwrap_or(NonZero::new(16).expect("16 is not 0")),
+            },
+
+            Target::Original(mode) => match from.port {
+                Single(x) => MappingKind::Transparent {
+                    from: SocketAddr::new(from.ip, x),
+                    mode,
+                },
+                Range(_) => return Err(TransparentPortRange),
             },
 
-            (Range(from_range), Single(to_port)) => MappingKind::ManyToOne {
+            Target::Pool(pool, port) => MappingKind::Pool {
                 from_ip: from.ip,
-                from_ports: from_range,
-                to: SocketAddr::new(to.ip, to_port),
+                from_ports: from.port.ports(),
+                pool,
+                port,
             },
 
-            (Range(from_range), Range(to_range)) => {
-                if from_range.len() != to_range.len() {
-                    return Err(InvalidPortRanges);
-                } else {
-                    MappingKind::ManyToMany {
-                        from_ip: from.ip,
-                        from_port_range_start: from_range.start,
-                        to_ip: to.ip,
-                        to_port_range_start: to_range.start,
-                        port_range_len: from_range
-                            .len()
-                            .try_into()
-                            .expect("the length of Range<u16> can never be greater than u16"),
+            Target::Address(to) => match (from.port.clone(), to.port) {
+                (Single(from_port), Single(to_port)) => MappingKind::OneToOne {
+                    from: SocketAddr::new(from.ip, from_port),
+                    to: SocketAddr::new(to.ip, to_port),
+                },
+
+                (Range(from_range), Single(to_port)) => MappingKind::ManyToOne {
+                    from_ip: from.ip,
+                    from_ports: from_range,
+                    to: SocketAddr::new(to.ip, to_port),
+                },
+
+                (Range(from_range), Range(to_range)) => {
+                    if from_range.len() != to_range.len() {
+                        return Err(InvalidPortRanges);
+                    } else {
+                        MappingKind::ManyToMany {
+                            from_ip: from.ip,
+                            from_port_range_start: from_range.start,
+                            to_ip: to.ip,
+                            to_port_range_start: to_range.start,
+                            port_range_len: from_range
+                                .len()
+                                .try_into()
+                                .expect("the length of Range<u16> can never be greater than u16"),
+                        }
                     }
                 }
+
+                (Single(_), Range(_)) => return Err(InvalidMappingType),
+            },
+        };
+
+        let wake_on = (wake_on_bytes != 0 || !wake_on_prefix.is_empty()).then_some(WakeOn {
+            min_bytes: wake_on_bytes,
+            prefix: wake_on_prefix,
+            timeout: wake_on_timeout,
+        });
+
+        let preamble = match (auth_key, auth_skew, auth_timeout) {
+            (Some(key), skew, timeout) => Some(Preamble {
+                key,
+                skew: skew.unwrap_or(Duration::from_secs(30)),
+                timeout: timeout.unwrap_or(Duration::from_secs(10)),
+                seen: Default::default(),
+            }),
+            (None, Some(_), _) => return Err(AuthSkewWithoutKey),
+            (None, _, Some(_)) => return Err(AuthTimeoutWithoutKey),
+            (None, None, None) => None,
+        };
+
+        let accept_proxy = match (accept_proxy, accept_proxy_timeout) {
+            (Some(from), timeout) => Some(proxy::AcceptProxy {
+                from,
+                timeout: timeout.unwrap_or(Duration::from_secs(10)),
+            }),
+            (None, Some(_)) => return Err(AcceptProxyTimeoutWithoutAcceptProxy),
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            kind,
+            opts,
+            wake_on,
+            access,
+            preamble,
+            fallback,
+            send_proxy,
+            accept_proxy,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseKnockError {
+    #[error("expected knock address and ports")]
+    ExpectedAddress,
+
+    #[error("invalid knock address: {0}")]
+    InvalidAddress(AddrParseError),
+
+    #[error("expected knock ports separated by ','")]
+    ExpectedPorts,
+
+    #[error("invalid knock port: {0}")]
+    InvalidPort(ParseIntError),
+
+    #[error("knock ports must not be in ascending order, a port scan would knock them")]
+    AscendingPorts,
+
+    #[error("expected literal 'to'")]
+    ExpectedTo,
+
+    #[error("expected upstream address")]
+    ExpectedUpstream,
+
+    #[error("invalid upstream address: {0}")]
+    InvalidUpstream(AddrParseError),
+
+    #[error("expected a window for 'window'")]
+    ExpectedWindow,
+
+    #[error("invalid knock window: {0}")]
+    InvalidWindow(ParseIntError),
+
+    #[error("expected a duration for 'grant'")]
+    ExpectedGrant,
+
+    #[error("invalid grant duration: {0}")]
+    InvalidGrant(ParseIntError),
+}
+
+impl Knock {
+    // Syntax: <ip>:<port>,<port>,... to <ip>
+    fn parse(stream: &mut ParseStream<'_>) -> Result<Self, ParseKnockError> {
+        use ParseKnockError::*;
+
+        let (ip, ports) = stream
+            .next()
+            .ok_or(ExpectedAddress)?
+            .rsplit_once(':')
+            .ok_or(ExpectedPorts)?;
+
+        let ip: IpAddr = ip
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(ip)
+            .parse()
+            .map_err(InvalidAddress)?;
+
+        let ports = ports
+            .split(',')
+            .map(|x| x.parse().map_err(InvalidPort))
+            .collect::<Result<Vec<u16>, _>>()?;
+
+        // Knocks on other ports do not reset progress, so an ordinary port
+        // scan goes through any ascending sequence.
+        if ports.is_sorted_by(|a, b| a < b) {
+            return Err(AscendingPorts);
+        }
+
+        if stream.next().ok_or(ExpectedTo)? != "to" {
+            return Err(ExpectedTo);
+        }
+
+        let upstream: IpAddr = stream
+            .next()
+            .ok_or(ExpectedUpstream)?
+            .parse()
+            .map_err(InvalidUpstream)?;
+
+        let mut window = Duration::from_secs(10);
+        let mut grant = Duration::from_secs(300);
+
+        while let Some(x) = stream.next() {
+            match x {
+                "window" => {
+                    window = stream
+                        .next()
+                        .ok_or(ExpectedWindow)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidWindow)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                "grant" => {
+                    grant = stream
+                        .next()
+                        .ok_or(ExpectedGrant)?
+                        .parse::<NonZero<u64>>()
+                        .map_err(InvalidGrant)
+                        .map(|x| Duration::from_secs(x.get()))?;
+                }
+
+                _ => {
+                    warn!("ignoring unknown property '{x}'");
+                }
             }
+        }
+
+        Ok(Self {
+            ip,
+            ports,
+            upstream,
+            window,
+            grant,
+            progress: Default::default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParsePoolError {
+    #[error("expected pool name")]
+    ExpectedName,
+
+    #[error("expected pool members separated by ','")]
+    ExpectedMembers,
+
+    #[error("invalid pool member: {0}")]
+    InvalidMember(AddrParseError),
+
+    #[error("expected 'round-robin' or 'least-connections' for 'balance'")]
+    ExpectedBalance,
+
+    #[error("unknown balancing strategy '{0}'")]
+    UnknownBalance(String),
+}
+
+impl Pool {
+    // Syntax: <name> <ip>,<ip>,...
+    fn parse(stream: &mut ParseStream<'_>) -> Result<(String, Self), ParsePoolError> {
+        use ParsePoolError::*;
 
-            (Single(_), Range(_)) => return Err(InvalidMappingType),
+        let name = stream.next().ok_or(ExpectedName)?.to_owned();
+
+        let members = stream
+            .next()
+            .ok_or(ExpectedMembers)?
+            .split(',')
+            .map(|x| x.parse().map_err(InvalidMember))
+            .collect::<Result<Vec<IpAddr>, _>>()?;
+
+        let mut balance = Balance::RoundRobin;
+
+        while let Some(x) = stream.next() {
+            match x {
+                "balance" => {
+                    balance = match stream.next().ok_or(ExpectedBalance)? {
+                        "round-robin" => Balance::RoundRobin,
+                        "least-connections" => Balance::LeastConnections,
+                        x => return Err(UnknownBalance(x.to_owned())),
+                    };
+                }
+
+                _ => {
+                    warn!("ignoring unknown property '{x}'");
+                }
+            }
+        }
+
+        let pool = Self {
+            members,
+            balance,
+            next: Default::default(),
         };
 
-        Ok(Self { kind, opts })
+        Ok((name, pool))
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseErrorKind {
+    Defaults(ParseDefaultsError),
     Upstream(ParseUpstreamError),
     Mapping(ParseMappingError),
+    Knock(ParseKnockError),
+    Pool(ParsePoolError),
     DuplicateUpstreamDirectives,
+    DuplicatePoolDirectives,
     UnknownUpstream(IpAddr),
+    UnknownPool(String),
+    DependencyCycle(Vec<IpAddr>),
+    UdpWithoutProbe(IpAddr),
+    PoolWithoutProbe(IpAddr),
 }
 
 impl fmt::Display for ParseErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Defaults(x) => x.fmt(f),
             Self::Upstream(x) => x.fmt(f),
             Self::Mapping(x) => x.fmt(f),
+            Self::Knock(x) => x.fmt(f),
+            Self::Pool(x) => x.fmt(f),
             Self::DuplicateUpstreamDirectives => f.write_str("duplicate upstream directives"),
+            Self::DuplicatePoolDirectives => f.write_str("duplicate pool directives"),
             Self::UnknownUpstream(x) => write!(f, "unknown upstream {}", display!(x)),
+            Self::UnknownPool(x) => write!(f, "unknown pool '{x}'"),
+            Self::UdpWithoutProbe(x) => write!(
+                f,
+                "'forward udp' requires a 'probe' on upstream {}",
+                display!(x)
+            ),
+            Self::PoolWithoutProbe(x) => write!(
+                f,
+                "pool members require a 'probe', upstream {} has none",
+                display!(x)
+            ),
+            Self::DependencyCycle(x) => {
+                f.write_str("circular dependency: ")?;
+
+                for (i, x) in x.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" -> ")?;
+                    }
+
+                    write!(f, "{}", display!(x))?;
+                }
+
+                Ok(())
+            }
         }
     }
 }
@@ -370,8 +1781,12 @@ impl FromStr for App {
     type Err = ParseError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut defaults = Defaults::default();
         let mut upstream = HashMap::new();
         let mut mappings = Vec::new();
+        let mut knocks = Vec::new();
+        let mut pools = HashMap::new();
+        let mut upstream_lines = Vec::new();
 
         let lines = s
             .lines()
@@ -387,13 +1802,21 @@ impl FromStr for App {
             let mut stream = SplitArgs::new(line).peekable();
 
             match stream.next().expect("line is not empty") {
+                "defaults" => {
+                    defaults
+                        .parse(&mut stream)
+                        .map_err(ParseErrorKind::Defaults)
+                        .map_err(parse_error)?;
+                }
+
                 "upstream" => {
-                    let x = Upstream::parse(&mut stream)
+                    let x = Upstream::parse(&mut stream, &defaults)
                         .map_err(ParseErrorKind::Upstream)
                         .map_err(parse_error)?;
 
                     match upstream.entry(x.address) {
                         Entry::Vacant(e) => {
+                            upstream_lines.push((x.address, i + 1));
                             e.insert(x);
                         }
                         Entry::Occupied(_) => {
@@ -407,20 +1830,301 @@ impl FromStr for App {
                         .map_err(ParseErrorKind::Mapping)
                         .map_err(parse_error)?;
 
-                    let y = x.kind.upstream();
-                    if !upstream.contains_key(&y) {
+                    if let Some(y) = x.kind.upstream()
+                        && !upstream.contains_key(&y)
+                    {
                         return Err(parse_error(ParseErrorKind::UnknownUpstream(y)));
                     }
 
+                    if let MappingKind::Pool { pool, .. } = &x.kind
+                        && !pools.contains_key(pool)
+                    {
+                        return Err(parse_error(ParseErrorKind::UnknownPool(pool.clone())));
+                    }
+
+                    // Without a connection, only a probe can tell that the
+                    // upstream is up.
+                    if let MappingKind::Udp { to, .. } = &x.kind
+                        && upstream[&to.ip()].probe.is_none()
+                    {
+                        return Err(parse_error(ParseErrorKind::UdpWithoutProbe(to.ip())));
+                    }
+
                     mappings.push(x);
                 }
 
+                "pool" => {
+                    let (name, x) = Pool::parse(&mut stream)
+                        .map_err(ParseErrorKind::Pool)
+                        .map_err(parse_error)?;
+
+                    if let Some(&y) = x.members.iter().find(|x| !upstream.contains_key(x)) {
+                        return Err(parse_error(ParseErrorKind::UnknownUpstream(y)));
+                    }
+
+                    // Only awake members are balanced between, and only a
+                    // probe tells that a member is awake before anything has
+                    // connected to it.
+                    if let Some(&y) = x.members.iter().find(|x| upstream[x].probe.is_none()) {
+                        return Err(parse_error(ParseErrorKind::PoolWithoutProbe(y)));
+                    }
+
+                    match pools.entry(name) {
+                        Entry::Vacant(e) => {
+                            e.insert(x);
+                        }
+                        Entry::Occupied(_) => {
+                            return Err(parse_error(ParseErrorKind::DuplicatePoolDirectives));
+                        }
+                    }
+                }
+
+                "knock" => {
+                    let x = Knock::parse(&mut stream)
+                        .map_err(ParseErrorKind::Knock)
+                        .map_err(parse_error)?;
+
+                    let Some(y) = upstream.get_mut(&x.upstream) else {
+                        return Err(parse_error(ParseErrorKind::UnknownUpstream(x.upstream)));
+                    };
+
+                    y.grants.get_or_insert_default();
+                    knocks.push(x);
+                }
+
                 x => {
                     warn!("skipping unknown directive '{x}'")
                 }
             }
         }
 
-        Ok(Self { upstream, mappings })
+        for &(address, line) in &upstream_lines {
+            for dep in &upstream[&address].requires {
+                if !upstream.contains_key(&dep.address) {
+                    return Err(ParseError {
+                        line,
+                        kind: ParseErrorKind::UnknownUpstream(dep.address),
+                    });
+                }
+            }
+        }
+
+        let mut done = HashSet::new();
+        for &(address, _) in &upstream_lines {
+            if let Some(cycle) = find_cycle(&upstream, &mut vec![address], &mut done) {
+                let at = cycle[cycle.len() - 2];
+                let &(_, line) = upstream_lines
+                    .iter()
+                    .find(|(x, _)| *x == at)
+                    .expect("upstream should have a line");
+
+                return Err(ParseError {
+                    line,
+                    kind: ParseErrorKind::DependencyCycle(cycle),
+                });
+            }
+        }
+
+        Ok(Self {
+            upstream,
+            mappings,
+            knocks,
+            pools,
+        })
+    }
+}
+
+// Depth-first search for a dependency cycle through the last upstream of
+// `path`. The cycle is returned with its first upstream repeated at the end.
+fn find_cycle(
+    upstream: &HashMap<IpAddr, Upstream>,
+    path: &mut Vec<IpAddr>,
+    done: &mut HashSet<IpAddr>,
+) -> Option<Vec<IpAddr>> {
+    let &current = path.last()?;
+
+    if done.contains(&current) {
+        return None;
+    }
+
+    for dep in &upstream[&current].requires {
+        if let Some(i) = path.iter().position(|&x| x == dep.address) {
+            let mut cycle = path[i..].to_vec();
+            cycle.push(dep.address);
+            return Some(cycle);
+        }
+
+        path.push(dep.address);
+        if let Some(x) = find_cycle(upstream, path, done) {
+            return Some(x);
+        }
+        path.pop();
+    }
+
+    done.insert(current);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    // One upstream per line, with its dependencies.
+    fn parse(upstream: &[(&str, &[&str])]) -> Result<App, ParseError> {
+        upstream
+            .iter()
+            .map(|(address, requires)| {
+                let mut line = format!("upstream {address} waker command wake-cmd true");
+                for x in *requires {
+                    line += &format!(" requires {x}:22");
+                }
+                line + "\n"
+            })
+            .collect::<String>()
+            .parse()
+    }
+
+    fn cycle(line: usize, x: &[&str]) -> ParseError {
+        ParseError {
+            line,
+            kind: ParseErrorKind::DependencyCycle(x.iter().map(|x| ip(x)).collect()),
+        }
+    }
+
+    #[test]
+    fn self_dependency() {
+        let e = parse(&[("10.0.0.1", &["10.0.0.1"])]).unwrap_err();
+
+        assert_eq!(e, cycle(1, &["10.0.0.1", "10.0.0.1"]));
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let e = parse(&[("10.0.0.1", &["10.0.0.2"]), ("10.0.0.2", &["10.0.0.1"])]).unwrap_err();
+
+        assert_eq!(e, cycle(2, &["10.0.0.1", "10.0.0.2", "10.0.0.1"]));
+    }
+
+    #[test]
+    fn longer_dependency_cycle() {
+        let e = parse(&[
+            ("10.0.0.1", &["10.0.0.2"]),
+            ("10.0.0.2", &["10.0.0.3"]),
+            ("10.0.0.3", &["10.0.0.4"]),
+            ("10.0.0.4", &["10.0.0.2"]),
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            e,
+            cycle(4, &["10.0.0.2", "10.0.0.3", "10.0.0.4", "10.0.0.2"])
+        );
+    }
+
+    #[test]
+    fn diamond_dependencies() {
+        let app = parse(&[
+            ("10.0.0.1", &["10.0.0.2", "10.0.0.3"]),
+            ("10.0.0.2", &["10.0.0.4"]),
+            ("10.0.0.3", &["10.0.0.4"]),
+            ("10.0.0.4", &[]),
+        ])
+        .unwrap();
+
+        assert_eq!(app.upstream[&ip("10.0.0.1")].requires.len(), 2);
+    }
+
+    #[test]
+    fn dependency_declared_later() {
+        let app = parse(&[("10.0.0.1", &["10.0.0.2"]), ("10.0.0.2", &[])]).unwrap();
+
+        let dep = app.upstream[&ip("10.0.0.1")].requires[0];
+        assert_eq!(dep.address, ip("10.0.0.2"));
+        assert_eq!(dep.port, 22);
+        assert!(dep.required);
+    }
+
+    #[test]
+    fn unknown_dependency() {
+        let e = parse(&[("10.0.0.1", &[]), ("10.0.0.2", &["10.0.0.3"])]).unwrap_err();
+
+        assert_eq!(
+            e,
+            ParseError {
+                line: 2,
+                kind: ParseErrorKind::UnknownUpstream(ip("10.0.0.3")),
+            }
+        );
+    }
+
+    #[test]
+    fn pool_without_probe() {
+        let e = "upstream 10.0.0.1 waker command wake-cmd true probe tcp 22
+                 upstream 10.0.0.2 waker command wake-cmd true
+                 pool p 10.0.0.1,10.0.0.2"
+            .parse::<App>()
+            .unwrap_err();
+
+        assert_eq!(
+            e,
+            ParseError {
+                line: 3,
+                kind: ParseErrorKind::PoolWithoutProbe(ip("10.0.0.2")),
+            }
+        );
+    }
+
+    #[test]
+    fn accept_proxy_from() {
+        let app = "upstream 10.0.0.1 waker command wake-cmd true
+                   forward 0.0.0.0:443 to 10.0.0.1:443 accept-proxy 192.168.0.0/24,fd00::1"
+            .parse::<App>()
+            .unwrap();
+
+        let x = app.mappings[0].accept_proxy.as_ref().unwrap();
+        assert!(x.trusts(ip("192.168.0.10")));
+        assert!(x.trusts(ip("fd00::1")));
+        assert!(!x.trusts(ip("192.168.1.10")));
+        assert_eq!(x.timeout, Duration::from_secs(10));
+
+        let e = "upstream 10.0.0.1 waker command wake-cmd true
+                 forward 0.0.0.0:443 to 10.0.0.1:443 accept-proxy"
+            .parse::<App>()
+            .unwrap_err();
+
+        assert_eq!(
+            e.kind,
+            ParseErrorKind::Mapping(ParseMappingError::ExpectedNetwork("accept-proxy"))
+        );
+    }
+
+    #[test]
+    fn ascending_knock() {
+        for ports in ["7000,8000,9000", "7000"] {
+            let e = format!(
+                "upstream 10.0.0.1 waker command wake-cmd true
+                 knock 0.0.0.0:{ports} to 10.0.0.1"
+            )
+            .parse::<App>()
+            .unwrap_err();
+
+            assert_eq!(
+                e.kind,
+                ParseErrorKind::Knock(ParseKnockError::AscendingPorts)
+            );
+        }
+
+        for ports in ["8000,7000,9000", "7000,7000", "9000,8000"] {
+            format!(
+                "upstream 10.0.0.1 waker command wake-cmd true
+                 knock 0.0.0.0:{ports} to 10.0.0.1"
+            )
+            .parse::<App>()
+            .unwrap();
+        }
     }
 }
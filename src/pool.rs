@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::power::PowerState;
+use crate::upstream::Upstream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    RoundRobin,
+    LeastConnections,
+}
+
+/// Interchangeable upstreams, in order of preference.
+#[derive(Debug)]
+pub struct Pool {
+    pub members: Vec<IpAddr>,
+    pub balance: Balance,
+    pub next: AtomicUsize,
+}
+
+impl Pool {
+    /// Choose the member to forward a new connection to.
+    ///
+    /// Awake members are balanced between. If none is awake, the member that
+    /// is already waking up is chosen, or else the most preferred one that is
+    /// not known to be unreachable, so that only one member is woken up.
+    pub fn pick(&self, upstream: &HashMap<IpAddr, Upstream>) -> IpAddr {
+        let state = |x: &IpAddr| upstream[x].power.get();
+
+        let awake: Vec<IpAddr> = self
+            .members
+            .iter()
+            .filter(|x| state(x) == PowerState::Awake)
+            .copied()
+            .collect();
+
+        if awake.is_empty() {
+            let waking = self.members.iter().find(|x| state(x) == PowerState::Waking);
+            let reachable = self
+                .members
+                .iter()
+                .find(|x| state(x) != PowerState::Unreachable);
+
+            return *waking.or(reachable).unwrap_or(&self.members[0]);
+        }
+
+        match self.balance {
+            Balance::RoundRobin => awake[self.next.fetch_add(1, Ordering::Relaxed) % awake.len()],
+            Balance::LeastConnections => *awake
+                .iter()
+                .min_by_key(|x| upstream[x].tunnels.count())
+                .expect("awake is not empty"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use PowerState::*;
+
+    fn app(balance: &str) -> App {
+        let mut config = String::new();
+        for i in 1..=3 {
+            config += &format!("upstream 10.0.0.{i} waker command wake-cmd true probe tcp 22\n");
+        }
+        config += &format!("pool p 10.0.0.1,10.0.0.2,10.0.0.3 balance {balance}\n");
+
+        config.parse().unwrap()
+    }
+
+    fn ip(i: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, i])
+    }
+
+    fn set(app: &App, states: [PowerState; 3]) {
+        for (i, x) in (1..).zip(states) {
+            app.upstream[&ip(i)].power.set(x);
+        }
+    }
+
+    fn pick(app: &App) -> IpAddr {
+        app.pools["p"].pick(&app.upstream)
+    }
+
+    #[test]
+    fn round_robin_between_awake_members() {
+        let app = app("round-robin");
+        set(&app, [Asleep, Awake, Awake]);
+
+        assert_eq!(pick(&app), ip(2));
+        assert_eq!(pick(&app), ip(3));
+        assert_eq!(pick(&app), ip(2));
+    }
+
+    #[test]
+    fn least_connections_between_awake_members() {
+        let app = app("least-connections");
+        set(&app, [Awake, Awake, Awake]);
+
+        let _a = app.upstream[&ip(1)].tunnels.open();
+        let _b = app.upstream[&ip(2)].tunnels.open();
+
+        assert_eq!(pick(&app), ip(3));
+
+        let _c = app.upstream[&ip(3)].tunnels.open();
+        let _d = app.upstream[&ip(3)].tunnels.open();
+
+        assert_eq!(pick(&app), ip(1));
+    }
+
+    #[test]
+    fn prefers_a_waking_member() {
+        let app = app("round-robin");
+        set(&app, [Asleep, Unknown, Waking]);
+
+        assert_eq!(pick(&app), ip(3));
+    }
+
+    #[test]
+    fn skips_unreachable_members() {
+        let app = app("round-robin");
+        set(&app, [Unreachable, Asleep, Unknown]);
+
+        assert_eq!(pick(&app), ip(2));
+    }
+
+    #[test]
+    fn falls_back_to_the_first_member() {
+        let app = app("round-robin");
+        set(&app, [Unreachable, Unreachable, Unreachable]);
+
+        assert_eq!(pick(&app), ip(1));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::io;
+
+use owo_colors::OwoColorize;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Unknown,
+    Asleep,
+    Waking,
+    Awake,
+    Unreachable,
+}
+
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => f.write_str("unknown"),
+            Self::Asleep => f.write_str("asleep"),
+            Self::Waking => f.write_str("waking"),
+            Self::Awake => f.write_str("awake"),
+            Self::Unreachable => f.write_str("unreachable"),
+        }
+    }
+}
+
+impl fmt::Display for crate::display::_display<'_, PowerState> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            PowerState::Awake => self.0.bright_green().fmt(f),
+            PowerState::Waking => self.0.bright_yellow().fmt(f),
+            PowerState::Unreachable => self.0.bright_red().fmt(f),
+            PowerState::Unknown | PowerState::Asleep => self.0.dimmed().fmt(f),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Power(watch::Sender<PowerState>);
+
+impl Default for Power {
+    fn default() -> Self {
+        Self(watch::Sender::new(PowerState::Unknown))
+    }
+}
+
+impl Power {
+    pub fn get(&self) -> PowerState {
+        *self.0.borrow()
+    }
+
+    pub fn set(&self, state: PowerState) {
+        let old = self.0.send_replace(state);
+
+        if old != state {
+            info!("upstream is {}", display!(state));
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<PowerState> {
+        self.0.subscribe()
+    }
+
+    /// Mark the upstream as waking up until the wake finishes.
+    pub fn waking(&self) -> Waking<'_> {
+        let previous = self.get();
+        self.set(PowerState::Waking);
+
+        Waking {
+            power: self,
+            previous: Some(previous),
+        }
+    }
+
+    /// Record the result of a presence probe.
+    ///
+    /// A failed probe does not override an ongoing wake or an upstream that
+    /// has already failed to wake up.
+    pub fn observe(&self, reachable: bool) {
+        let state = match (reachable, self.get()) {
+            (true, _) => PowerState::Awake,
+            (false, PowerState::Waking | PowerState::Unreachable) => return,
+            (false, _) => PowerState::Asleep,
+        };
+
+        self.set(state);
+    }
+}
+
+/// A wake in progress. If it is abandoned before it finishes, the upstream
+/// goes back to the state it was in before.
+#[derive(Debug)]
+pub struct Waking<'a> {
+    power: &'a Power,
+    previous: Option<PowerState>,
+}
+
+impl Waking<'_> {
+    pub fn finish<T>(mut self, result: &io::Result<T>) {
+        self.previous = None;
+
+        self.power.set(match result {
+            Ok(_) => PowerState::Awake,
+            Err(_) => PowerState::Unreachable,
+        });
+    }
+}
+
+impl Drop for Waking<'_> {
+    fn drop(&mut self) {
+        if let Some(x) = self.previous {
+            debug!("wake was abandoned");
+            self.power.set(x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_wake() {
+        let power = Power::default();
+
+        let waking = power.waking();
+        assert_eq!(power.get(), PowerState::Waking);
+
+        waking.finish(&Ok(()));
+        assert_eq!(power.get(), PowerState::Awake);
+
+        power
+            .waking()
+            .finish::<()>(&Err(io::ErrorKind::TimedOut.into()));
+        assert_eq!(power.get(), PowerState::Unreachable);
+    }
+
+    #[test]
+    fn abandoned_wake() {
+        let power = Power::default();
+        power.set(PowerState::Asleep);
+
+        drop(power.waking());
+        assert_eq!(power.get(), PowerState::Asleep);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::timeout;
+
+use crate::util::getrandom;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TAG_LEN: usize = 32;
+
+/// The length of a preamble: a big-endian UNIX timestamp in seconds and a
+/// random nonce, followed by an HMAC-SHA256 of both.
+pub const LEN: usize = 8 + 8 + TAG_LEN;
+
+pub struct Key(Vec<u8>);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl From<&str> for Key {
+    fn from(value: &str) -> Self {
+        Self(value.as_bytes().to_vec())
+    }
+}
+
+impl Key {
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac
+    }
+
+    pub fn sign(&self) -> io::Result<[u8; LEN]> {
+        self.sign_at(now())
+    }
+
+    fn sign_at(&self, timestamp: u64) -> io::Result<[u8; LEN]> {
+        let mut preamble = [0u8; LEN];
+        let (data, tag) = preamble.split_at_mut(16);
+
+        data[..8].copy_from_slice(&timestamp.to_be_bytes());
+        getrandom(&mut data[8..])?;
+
+        tag.copy_from_slice(&self.mac(data).finalize().into_bytes());
+        Ok(preamble)
+    }
+}
+
+/// Require clients to authenticate with a preamble before anything else.
+#[derive(Debug)]
+pub struct Preamble {
+    pub key: Key,
+    pub skew: Duration,
+    pub timeout: Duration,
+    pub seen: Mutex<HashMap<[u8; TAG_LEN], u64>>,
+}
+
+impl Preamble {
+    /// Read the preamble from `client` and check it. The preamble is consumed
+    /// so that it is not forwarded to the upstream.
+    pub async fn verify(&self, client: &mut (impl AsyncRead + Unpin)) -> io::Result<()> {
+        let mut preamble = [0u8; LEN];
+
+        timeout(self.timeout, client.read_exact(&mut preamble))
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "client did not authenticate in time",
+                )
+            })??;
+
+        let (data, tag) = preamble.split_at(16);
+
+        self.key
+            .mac(data)
+            .verify_slice(tag)
+            .map_err(|_| invalid("invalid preamble signature"))?;
+
+        let timestamp = u64::from_be_bytes(data[..8].try_into().expect("8 bytes"));
+        let now = now();
+
+        if now.abs_diff(timestamp) > self.skew.as_secs() {
+            return Err(invalid("preamble has expired"));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, &mut x| now.abs_diff(x) <= self.skew.as_secs());
+
+        let tag = tag.try_into().expect("tag length");
+        if seen.insert(tag, timestamp).is_some() {
+            return Err(invalid("preamble has already been used"));
+        }
+
+        debug!("client authenticated");
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, msg)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preamble(key: &str) -> Preamble {
+        Preamble {
+            key: key.into(),
+            skew: Duration::from_secs(30),
+            timeout: Duration::from_secs(1),
+            seen: Default::default(),
+        }
+    }
+
+    async fn verify(p: &Preamble, data: &[u8]) -> io::Result<()> {
+        p.verify(&mut &data[..]).await
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        let p = preamble("secret");
+        let x = p.key.sign().unwrap();
+
+        verify(&p, &x).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_key() {
+        let p = preamble("secret");
+        let x = Key::from("not the secret").sign().unwrap();
+
+        let e = verify(&p, &x).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn tampered() {
+        let p = preamble("secret");
+
+        for i in [0, 8, 16, LEN - 1] {
+            let mut x = p.key.sign().unwrap();
+            x[i] ^= 1;
+
+            let e = verify(&p, &x).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let p = preamble("secret");
+        let x = p.key.sign().unwrap();
+
+        let e = verify(&p, &x[..LEN - 1]).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn within_skew() {
+        let p = preamble("secret");
+
+        verify(&p, &p.key.sign_at(now() - 20).unwrap())
+            .await
+            .unwrap();
+        verify(&p, &p.key.sign_at(now() + 20).unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let p = preamble("secret");
+
+        for x in [now() - 60, now() + 60, 0, u64::MAX] {
+            let e = verify(&p, &p.key.sign_at(x).unwrap()).await.unwrap_err();
+            assert_eq!(e.to_string(), "preamble has expired");
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed() {
+        let p = preamble("secret");
+        let x = p.key.sign().unwrap();
+
+        verify(&p, &x).await.unwrap();
+
+        let e = verify(&p, &x).await.unwrap_err();
+        assert_eq!(e.to_string(), "preamble has already been used");
+
+        verify(&p, &p.key.sign().unwrap()).await.unwrap();
+    }
+}
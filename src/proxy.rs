@@ -0,0 +1,470 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::timeout;
+
+use crate::access::Cidr;
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// The longest possible v1 header, including the CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Peers that may start connections with a PROXY header.
+#[derive(Debug)]
+pub struct AcceptProxy {
+    pub from: Vec<Cidr>,
+    pub timeout: Duration,
+}
+
+impl AcceptProxy {
+    pub fn trusts(&self, peer: IpAddr) -> bool {
+        self.from.iter().any(|x| x.contains(peer))
+    }
+}
+
+/// PROXY protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+/// A PROXY protocol header for a TCP connection from `src` to `dst`.
+pub fn header(version: Version, src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let (src_ip, dst_ip) = match (src.ip().to_canonical(), dst.ip().to_canonical()) {
+        (IpAddr::V4(x), IpAddr::V6(y)) => (IpAddr::V6(x.to_ipv6_mapped()), IpAddr::V6(y)),
+        (IpAddr::V6(x), IpAddr::V4(y)) => (IpAddr::V6(x), IpAddr::V6(y.to_ipv6_mapped())),
+        x => x,
+    };
+
+    match version {
+        Version::V1 => {
+            let family = match src_ip {
+                IpAddr::V4(_) => "TCP4",
+                IpAddr::V6(_) => "TCP6",
+            };
+
+            format!(
+                "PROXY {family} {src_ip} {dst_ip} {} {}\r\n",
+                src.port(),
+                dst.port()
+            )
+            .into_bytes()
+        }
+
+        Version::V2 => {
+            let mut buf = SIGNATURE.to_vec();
+            // Version 2, PROXY command.
+            buf.push(0x21);
+
+            let addresses = match (src_ip, dst_ip) {
+                (IpAddr::V4(x), IpAddr::V4(y)) => {
+                    buf.push(0x11);
+                    [x.octets().as_slice(), &y.octets()].concat()
+                }
+                (IpAddr::V6(x), IpAddr::V6(y)) => {
+                    buf.push(0x21);
+                    [x.octets().as_slice(), &y.octets()].concat()
+                }
+                _ => unreachable!("families were made to match"),
+            };
+
+            let len = (addresses.len() + 4) as u16;
+            buf.extend_from_slice(&len.to_be_bytes());
+            buf.extend_from_slice(&addresses);
+            buf.extend_from_slice(&src.port().to_be_bytes());
+            buf.extend_from_slice(&dst.port().to_be_bytes());
+            buf
+        }
+    }
+}
+
+/// Read a v1 or v2 PROXY protocol header from a client.
+///
+/// Returns the original source and destination addresses, or `None` when the
+/// header does not carry any, like health checks from the balancer itself.
+pub async fn accept(
+    client: &mut (impl AsyncRead + Unpin),
+    within: Duration,
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    timeout(within, read(client)).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            "client did not send a PROXY header in time",
+        )
+    })?
+}
+
+async fn read(
+    client: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    // Both versions are at least this long.
+    let mut start = [0u8; 12];
+    client.read_exact(&mut start).await?;
+
+    if start == SIGNATURE {
+        return read_v2(client).await;
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY header"));
+    }
+
+    // Read byte by byte, so nothing after the header is consumed.
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY header is too long"));
+        }
+
+        line.push(client.read_u8().await?);
+    }
+
+    let line = str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY header is not ASCII"))?;
+
+    parse_v1(line).ok_or_else(|| invalid("malformed PROXY header"))
+}
+
+fn parse_v1(line: &str) -> Option<Option<(SocketAddr, SocketAddr)>> {
+    let mut parts = line.split(' ');
+
+    if parts.next()? != "PROXY" {
+        return None;
+    }
+
+    let v4 = match parts.next()? {
+        "TCP4" => true,
+        "TCP6" => false,
+        // The rest of the line may be anything.
+        "UNKNOWN" => return Some(None),
+        _ => return None,
+    };
+
+    let src: IpAddr = parts.next()?.parse().ok()?;
+    let dst: IpAddr = parts.next()?.parse().ok()?;
+    let src_port = port(parts.next()?)?;
+    let dst_port = port(parts.next()?)?;
+
+    if parts.next().is_some() || src.is_ipv4() != v4 || dst.is_ipv4() != v4 {
+        return None;
+    }
+
+    Some(Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+    )))
+}
+
+fn port(s: &str) -> Option<u16> {
+    if (s.len() > 1 && s.starts_with('0')) || !s.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+async fn read_v2(
+    client: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let version_command = client.read_u8().await?;
+    let family = client.read_u8().await?;
+    let len = client.read_u16().await?;
+
+    let mut body = vec![0u8; len.into()];
+    client.read_exact(&mut body).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0xf {
+        // LOCAL, the connection was made by the balancer itself.
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unknown PROXY command")),
+    }
+
+    let addresses = match family {
+        0x00 => None,
+        0x11 if body.len() >= 12 => {
+            let ip =
+                |x: &[u8]| IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(x).expect("4 bytes")));
+
+            Some((
+                SocketAddr::new(ip(&body[0..4]), u16::from_be_bytes([body[8], body[9]])),
+                SocketAddr::new(ip(&body[4..8]), u16::from_be_bytes([body[10], body[11]])),
+            ))
+        }
+        0x21 if body.len() >= 36 => {
+            let ip =
+                |x: &[u8]| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(x).expect("16 bytes")));
+
+            Some((
+                SocketAddr::new(ip(&body[0..16]), u16::from_be_bytes([body[32], body[33]])),
+                SocketAddr::new(ip(&body[16..32]), u16::from_be_bytes([body[34], body[35]])),
+            ))
+        }
+        0x11 | 0x21 => return Err(invalid("PROXY header is too short")),
+        _ => return Err(invalid("unsupported PROXY address family")),
+    };
+
+    Ok(addresses)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Addresses = Option<(SocketAddr, SocketAddr)>;
+
+    const SRC: &str = "192.168.0.2:51234";
+    const DST: &str = "10.0.0.1:443";
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    // Accept a header from `data` and return what is left after it.
+    async fn accept_from(data: &[u8]) -> io::Result<(Addresses, &[u8])> {
+        let mut rest = data;
+        let x = accept(&mut rest, Duration::from_secs(1)).await?;
+        Ok((x, rest))
+    }
+
+    async fn error(data: &[u8]) -> String {
+        accept_from(data).await.unwrap_err().to_string()
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (x, rest) = accept_from(b"PROXY TCP4 192.168.0.2 10.0.0.1 51234 443\r\nGET /")
+            .await
+            .unwrap();
+
+        assert_eq!(x, Some((addr(SRC), addr(DST))));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (x, _) = accept_from(b"PROXY TCP6 fd00::2 fd00::1 51234 443\r\n")
+            .await
+            .unwrap();
+
+        assert_eq!(x, Some((addr("[fd00::2]:51234"), addr("[fd00::1]:443"))));
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (x, rest) = accept_from(b"PROXY UNKNOWN whatever\r\nrest")
+            .await
+            .unwrap();
+
+        assert_eq!(x, None);
+        assert_eq!(rest, b"rest");
+
+        let (x, _) = accept_from(b"PROXY UNKNOWN\r\n").await.unwrap();
+        assert_eq!(x, None);
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        for x in [
+            &b"PROXY TCP4 fd00::2 10.0.0.1 51234 443\r\n"[..],
+            b"PROXY TCP6 192.168.0.2 10.0.0.1 51234 443\r\n",
+            b"PROXY TCP4 192.168.0.2 10.0.0.1 051234 443\r\n",
+            b"PROXY TCP4 192.168.0.2 10.0.0.1 65536 443\r\n",
+            b"PROXY TCP4 192.168.0.2 10.0.0.1 +1 443\r\n",
+            b"PROXY TCP4 192.168.0.2 10.0.0.1 51234\r\n",
+            b"PROXY TCP4 192.168.0.2 10.0.0.1 51234 443 extra\r\n",
+            b"PROXY TCP4  192.168.0.2 10.0.0.1 51234 443\r\n",
+            b"PROXY UDP4 192.168.0.2 10.0.0.1 51234 443\r\n",
+        ] {
+            assert_eq!(error(x).await, "malformed PROXY header");
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut x = b"PROXY UNKNOWN ".to_vec();
+        x.resize(200, b'a');
+        x.extend_from_slice(b"\r\n");
+
+        assert_eq!(error(&x).await, "PROXY header is too long");
+    }
+
+    #[tokio::test]
+    async fn v1_longest() {
+        let mut x = b"PROXY UNKNOWN ".to_vec();
+        x.resize(V1_MAX_LEN - 2, b'a');
+        x.extend_from_slice(b"\r\n");
+
+        assert_eq!(accept_from(&x).await.unwrap().0, None);
+    }
+
+    #[tokio::test]
+    async fn v1_truncated() {
+        let e = accept_from(b"PROXY TCP4 192.168.0.2").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        let e = accept_from(b"PROXY").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn v1_not_ascii() {
+        assert_eq!(
+            error(b"PROXY TCP4 \xff\r\n").await,
+            "PROXY header is not ASCII"
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_signature() {
+        assert_eq!(
+            error(b"GET / HTTP/1.1\r\n\r\n").await,
+            "missing PROXY header"
+        );
+
+        let mut x = header(Version::V2, addr(SRC), addr(DST));
+        x[11] = b'X';
+        assert_eq!(error(&x).await, "missing PROXY header");
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        for version in [Version::V1, Version::V2] {
+            for (src, dst) in [
+                (addr(SRC), addr(DST)),
+                (addr("[fd00::2]:51234"), addr("[fd00::1]:443")),
+            ] {
+                let mut x = header(version, src, dst);
+                x.extend_from_slice(b"payload");
+
+                let (y, rest) = accept_from(&x).await.unwrap();
+                assert_eq!(y, Some((src, dst)));
+                assert_eq!(rest, b"payload");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_families_are_mapped() {
+        let x = header(Version::V1, addr(SRC), addr("[fd00::1]:443"));
+
+        assert_eq!(
+            x,
+            b"PROXY TCP6 ::ffff:192.168.0.2 fd00::1 51234 443\r\n".to_vec()
+        );
+    }
+
+    fn v2(version_command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut x = SIGNATURE.to_vec();
+        x.push(version_command);
+        x.push(family);
+        x.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        x.extend_from_slice(body);
+        x
+    }
+
+    const V4_BODY: [u8; 12] = [192, 168, 0, 2, 10, 0, 0, 1, 0xc8, 0x22, 0x01, 0xbb];
+
+    #[tokio::test]
+    async fn v2_skips_tlvs() {
+        let mut body = V4_BODY.to_vec();
+        body.extend_from_slice(&[0x04, 0x00, 0x02, 0xab, 0xcd]);
+
+        let mut x = v2(0x21, 0x11, &body);
+        x.extend_from_slice(b"payload");
+
+        let (y, rest) = accept_from(&x).await.unwrap();
+        assert_eq!(y, Some((addr(SRC), addr(DST))));
+        assert_eq!(rest, b"payload");
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut x = v2(0x20, 0x11, &V4_BODY);
+        x.extend_from_slice(b"payload");
+
+        let (y, rest) = accept_from(&x).await.unwrap();
+        assert_eq!(y, None);
+        assert_eq!(rest, b"payload");
+    }
+
+    #[tokio::test]
+    async fn v2_unspecified_family() {
+        let (y, _) = accept_from(&v2(0x21, 0x00, &[])).await.unwrap();
+        assert_eq!(y, None);
+    }
+
+    #[tokio::test]
+    async fn v2_bad_version() {
+        assert_eq!(
+            error(&v2(0x11, 0x11, &V4_BODY)).await,
+            "unsupported PROXY protocol version"
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_bad_command() {
+        assert_eq!(
+            error(&v2(0x22, 0x11, &V4_BODY)).await,
+            "unknown PROXY command"
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_unsupported_family() {
+        // AF_UNIX stream.
+        assert_eq!(
+            error(&v2(0x21, 0x31, &[0; 216])).await,
+            "unsupported PROXY address family"
+        );
+
+        // TCP over IPv4 with an invalid transport nibble.
+        assert_eq!(
+            error(&v2(0x21, 0x13, &V4_BODY)).await,
+            "unsupported PROXY address family"
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_too_short_for_family() {
+        assert_eq!(
+            error(&v2(0x21, 0x11, &V4_BODY[..11])).await,
+            "PROXY header is too short"
+        );
+        assert_eq!(
+            error(&v2(0x21, 0x21, &[0; 35])).await,
+            "PROXY header is too short"
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_oversize_length() {
+        let mut x = v2(0x21, 0x11, &V4_BODY);
+        x[14..16].copy_from_slice(&u16::MAX.to_be_bytes());
+
+        let e = accept_from(&x).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn v2_truncated() {
+        let x = v2(0x21, 0x11, &V4_BODY);
+
+        for len in [13, 15, 20] {
+            let e = accept_from(&x[..len]).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Connection errors after which connecting to an upstream is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryOn {
+    Refused,
+    HostUnreachable,
+    NetworkUnreachable,
+    Timeout,
+}
+
+impl RetryOn {
+    pub const ALL: [Self; 4] = [
+        Self::Refused,
+        Self::HostUnreachable,
+        Self::NetworkUnreachable,
+        Self::Timeout,
+    ];
+
+    pub fn of(e: &io::Error) -> Option<Self> {
+        use io::ErrorKind::*;
+
+        match e.kind() {
+            ConnectionRefused => Some(Self::Refused),
+            HostUnreachable => Some(Self::HostUnreachable),
+            NetworkUnreachable => Some(Self::NetworkUnreachable),
+            TimedOut => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RetryOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Refused => f.write_str("connection refused"),
+            Self::HostUnreachable => f.write_str("host unreachable"),
+            Self::NetworkUnreachable => f.write_str("network unreachable"),
+            Self::Timeout => f.write_str("connection timed out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error(
+    "unknown condition '{0}', expected 'refused', 'host-unreachable', 'network-unreachable' or 'timeout'"
+)]
+pub struct ParseRetryOnError(String);
+
+impl FromStr for RetryOn {
+    type Err = ParseRetryOnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refused" => Ok(Self::Refused),
+            "host-unreachable" => Ok(Self::HostUnreachable),
+            "network-unreachable" => Ok(Self::NetworkUnreachable),
+            "timeout" => Ok(Self::Timeout),
+            x => Err(ParseRetryOnError(x.to_owned())),
+        }
+    }
+}
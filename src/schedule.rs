@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZero;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::backoff::{Backoff, Strategy};
+use crate::upstream::{ConnectOpts, Upstream};
+use crate::waker::WakeContext;
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseScheduleError {
+    #[error("unknown day '{0}', expected one of 'daily', 'sun', 'mon', ... 'sat'")]
+    UnknownDay(String),
+
+    #[error("invalid time '{0}', expected 'hh:mm'")]
+    InvalidTime(String),
+
+    #[error("expected a range of times like '00:00-07:00'")]
+    ExpectedRange,
+}
+
+/// A set of days of the week, starting from sunday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Days(u8);
+
+impl Days {
+    fn contains(&self, day: u8) -> bool {
+        self.0 & (1 << day) != 0
+    }
+}
+
+fn day(s: &str) -> Result<u8, ParseScheduleError> {
+    DAYS.iter()
+        .position(|&x| x == s)
+        .map(|x| x as u8)
+        .ok_or_else(|| ParseScheduleError::UnknownDay(s.to_owned()))
+}
+
+impl FromStr for Days {
+    type Err = ParseScheduleError;
+
+    // Syntax: daily | <day>[-<day>][,...]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "daily" {
+            return Ok(Self(0x7f));
+        }
+
+        let mut days = 0;
+
+        for x in s.split(',') {
+            let (start, end) = match x.split_once('-') {
+                Some((start, end)) => (day(start)?, day(end)?),
+                None => (day(x)?, day(x)?),
+            };
+
+            let mut d = start;
+            loop {
+                days |= 1 << d;
+
+                if d == end {
+                    break;
+                }
+
+                d = (d + 1) % 7;
+            }
+        }
+
+        Ok(Self(days))
+    }
+}
+
+/// Minutes since midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u16);
+
+impl FromStr for TimeOfDay {
+    type Err = ParseScheduleError;
+
+    // Syntax: hh:mm
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseScheduleError::InvalidTime(s.to_owned());
+
+        let (h, m) = s.split_once(':').ok_or_else(invalid)?;
+        let h: u16 = h.parse().map_err(|_| invalid())?;
+        let m: u16 = m.parse().map_err(|_| invalid())?;
+
+        if h > 23 || m > 59 {
+            return Err(invalid());
+        }
+
+        Ok(Self(h * 60 + m))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LocalTime {
+    day: u8,
+    time: TimeOfDay,
+}
+
+impl LocalTime {
+    fn now() -> Option<Self> {
+        let t = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return None;
+        }
+
+        Some(Self {
+            day: tm.tm_wday as u8,
+            time: TimeOfDay((tm.tm_hour * 60 + tm.tm_min) as u16),
+        })
+    }
+}
+
+/// A time window on some days. Windows that end before they start wrap
+/// around midnight into the next day.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub days: Days,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl Window {
+    // Syntax: <days> <hh:mm>-<hh:mm>
+    pub fn parse(days: &str, range: &str) -> Result<Self, ParseScheduleError> {
+        let (start, end) = range
+            .split_once('-')
+            .ok_or(ParseScheduleError::ExpectedRange)?;
+
+        Ok(Self {
+            days: days.parse()?,
+            start: start.parse()?,
+            end: end.parse()?,
+        })
+    }
+
+    fn contains(&self, now: LocalTime) -> bool {
+        let yesterday = (now.day + 6) % 7;
+
+        if self.start <= self.end {
+            self.days.contains(now.day) && self.start <= now.time && now.time < self.end
+        } else {
+            (self.days.contains(now.day) && now.time >= self.start)
+                || (self.days.contains(yesterday) && now.time < self.end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PreWake {
+    pub days: Days,
+    pub at: TimeOfDay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietMode {
+    Refuse,
+    AwakeOnly,
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub quiet: Vec<Window>,
+    pub quiet_mode: QuietMode,
+    pub pre_wake: Vec<PreWake>,
+    pub pre_wake_timeout: Duration,
+}
+
+impl Schedule {
+    pub fn is_quiet(&self) -> bool {
+        if self.quiet.is_empty() {
+            return false;
+        }
+
+        match LocalTime::now() {
+            Some(now) => self.quiet.iter().any(|x| x.contains(now)),
+            None => false,
+        }
+    }
+}
+
+impl Upstream {
+    pub async fn pre_wake(&self, upstream: &HashMap<IpAddr, Upstream>) {
+        if self.schedule.pre_wake.is_empty() {
+            return;
+        }
+
+        loop {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            sleep(Duration::from_secs(60 - now % 60)).await;
+
+            let Some(now) = LocalTime::now() else {
+                warn!("cannot determine the local time");
+                continue;
+            };
+
+            let due = self
+                .schedule
+                .pre_wake
+                .iter()
+                .any(|x| x.days.contains(now.day) && x.at == now.time);
+
+            if !due {
+                continue;
+            }
+
+            info!("scheduled wake");
+
+            // The same wake as for clients, so that clients connecting in the
+            // meantime wait for it instead of waking the upstream up again.
+            // Nobody is waiting on the other end yet, so keep probing until
+            // 'pre-wake-timeout' instead of giving up after a few attempts.
+            let opts = ConnectOpts {
+                max_attempts: NonZero::<u64>::MAX,
+                backoff: Backoff {
+                    strategy: Strategy::Exponential,
+                    delay: Duration::from_secs(1),
+                    factor: 2.0,
+                    max_delay: Some(Duration::from_secs(30)),
+                },
+                deadline: Some(self.schedule.pre_wake_timeout),
+                ..ConnectOpts::default()
+            };
+
+            let ctx = WakeContext::default();
+
+            if let Err(e) = self.wake_up(&opts, &ctx, upstream).await {
+                warn!("failed to wake upstream: {}", display!(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: &str, time: &str) -> LocalTime {
+        LocalTime {
+            day: super::day(day).unwrap(),
+            time: time.parse().unwrap(),
+        }
+    }
+
+    fn window(days: &str, range: &str) -> Window {
+        Window::parse(days, range).unwrap()
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let w = window("mon", "09:00-17:00");
+
+        assert!(!w.contains(at("mon", "08:59")));
+        assert!(w.contains(at("mon", "09:00")));
+        assert!(w.contains(at("mon", "16:59")));
+        assert!(!w.contains(at("mon", "17:00")));
+        assert!(!w.contains(at("tue", "10:00")));
+    }
+
+    #[test]
+    fn window_across_midnight() {
+        let w = window("mon", "23:00-07:00");
+
+        assert!(!w.contains(at("mon", "22:59")));
+        assert!(w.contains(at("mon", "23:00")));
+        assert!(w.contains(at("mon", "23:59")));
+        assert!(w.contains(at("tue", "00:00")));
+        assert!(w.contains(at("tue", "06:59")));
+        assert!(!w.contains(at("tue", "07:00")));
+        assert!(!w.contains(at("tue", "23:30")));
+
+        // The morning belongs to the night of the day before.
+        assert!(!w.contains(at("mon", "06:00")));
+    }
+
+    #[test]
+    fn window_across_the_end_of_the_week() {
+        let w = window("sat", "22:00-02:00");
+
+        assert!(w.contains(at("sat", "23:00")));
+        assert!(w.contains(at("sun", "01:00")));
+        assert!(!w.contains(at("sun", "23:00")));
+
+        let w = window("sun", "23:00-01:00");
+
+        assert!(w.contains(at("mon", "00:30")));
+        assert!(!w.contains(at("sun", "00:30")));
+    }
+
+    #[test]
+    fn empty_window() {
+        let w = window("daily", "00:00-00:00");
+
+        assert!(!w.contains(at("mon", "00:00")));
+        assert!(!w.contains(at("mon", "12:00")));
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!("daily".parse(), Ok(Days(0x7f)));
+        assert_eq!("sun".parse(), Ok(Days(0b1)));
+        assert_eq!("mon-fri".parse(), Ok(Days(0b0111110)));
+        assert_eq!("sat,sun".parse(), Ok(Days(0b1000001)));
+        assert_eq!("fri-mon".parse(), Ok(Days(0b1100011)));
+        assert_eq!(
+            "mon-funday".parse::<Days>(),
+            Err(ParseScheduleError::UnknownDay("funday".to_owned()))
+        );
+    }
+
+    #[test]
+    fn time_of_day() {
+        assert_eq!("00:00".parse(), Ok(TimeOfDay(0)));
+        assert_eq!("23:59".parse(), Ok(TimeOfDay(23 * 60 + 59)));
+
+        for x in ["24:00", "12:60", "12", "12:", ":30", "-1:00"] {
+            assert_eq!(
+                x.parse::<TimeOfDay>(),
+                Err(ParseScheduleError::InvalidTime(x.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn window_requires_a_range() {
+        assert!(matches!(
+            Window::parse("daily", "07:00"),
+            Err(ParseScheduleError::ExpectedRange)
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::hardware_addr::{self, HardwareAddr};
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Password {
+    Short([u8; 4]),
+    Long([u8; 6]),
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password(..)")
+    }
+}
+
+impl Password {
+    pub fn octets(&self) -> &[u8] {
+        match self {
+            Self::Short(x) => x,
+            Self::Long(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("expected 4 octets separated by '.' or 6 octets separated by ':'")]
+    UnknownFormat,
+    #[error("invalid octet: {0}")]
+    InvalidOctet(ParseIntError),
+    #[error("{0}")]
+    Long(hardware_addr::ParseError),
+}
+
+impl FromStr for Password {
+    type Err = ParseError;
+
+    // Syntax: <d>.<d>.<d>.<d> | <x>:<x>:<x>:<x>:<x>:<x>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            let x: HardwareAddr = s.parse().map_err(ParseError::Long)?;
+
+            let mut octets = [0u8; 6];
+            octets.copy_from_slice(x.octets());
+            return Ok(Self::Long(octets));
+        }
+
+        let mut octets = [0u8; 4];
+        let mut parts = s.split('.');
+
+        for x in octets.iter_mut() {
+            let octet = parts.next().ok_or(ParseError::UnknownFormat)?;
+            *x = octet.parse().map_err(ParseError::InvalidOctet)?;
+        }
+
+        if parts.next().is_some() {
+            return Err(ParseError::UnknownFormat);
+        }
+
+        Ok(Self::Short(octets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Password, ParseError> {
+        s.parse()
+    }
+
+    #[test]
+    fn dotted_decimal() {
+        assert_eq!(
+            parse("192.168.0.255"),
+            Ok(Password::Short([192, 168, 0, 255]))
+        );
+        assert_eq!(parse("0.0.0.0"), Ok(Password::Short([0; 4])));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(
+            parse("01:23:45:67:89:ab"),
+            Ok(Password::Long([0x01, 0x23, 0x45, 0x67, 0x89, 0xab]))
+        );
+        assert_eq!(
+            parse("FF:ff:0:0:0:0"),
+            Ok(Password::Long([0xff, 0xff, 0, 0, 0, 0]))
+        );
+    }
+
+    #[test]
+    fn wrong_length() {
+        assert_eq!(parse("1.2.3"), Err(ParseError::UnknownFormat));
+        assert_eq!(parse("1.2.3.4.5"), Err(ParseError::UnknownFormat));
+
+        assert_eq!(
+            parse("01:23:45:67:89"),
+            Err(ParseError::Long(hardware_addr::ParseError::ExpectedOctet))
+        );
+        assert!(matches!(
+            parse("01:23:45:67:89:ab:cd"),
+            Err(ParseError::Long(hardware_addr::ParseError::InvalidOctet(_)))
+        ));
+    }
+
+    #[test]
+    fn octet_out_of_range() {
+        assert!(matches!(
+            parse("1.2.3.256"),
+            Err(ParseError::InvalidOctet(_))
+        ));
+        assert!(matches!(
+            parse("1.2.-3.4"),
+            Err(ParseError::InvalidOctet(_))
+        ));
+        assert!(matches!(
+            parse("01:23:45:67:89:100"),
+            Err(ParseError::Long(hardware_addr::ParseError::InvalidOctet(_)))
+        ));
+    }
+
+    #[test]
+    fn bad_separators() {
+        assert!(matches!(parse("1-2-3-4"), Err(ParseError::InvalidOctet(_))));
+        assert!(matches!(parse("1..2.3"), Err(ParseError::InvalidOctet(_))));
+        assert!(matches!(parse("1.2.3.4."), Err(ParseError::UnknownFormat)));
+        assert!(matches!(
+            parse("01-23-45-67-89-ab"),
+            Err(ParseError::InvalidOctet(_))
+        ));
+        assert!(matches!(parse("1.2.3:4"), Err(ParseError::Long(_))));
+    }
+}
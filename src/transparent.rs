@@ -0,0 +1,112 @@
+use std::io;
+use std::mem::size_of;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::AsRawFd;
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How intercepted connections are sent to the listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interception {
+    /// NAT REDIRECT, the original destination is kept by conntrack.
+    Redirect,
+    /// TPROXY, connections are accepted on their original destination.
+    Tproxy,
+}
+
+pub fn bind(from: SocketAddr, mode: Interception) -> io::Result<TcpListener> {
+    let s = Socket::new(Domain::for_address(from), Type::STREAM, Some(Protocol::TCP))?;
+    s.set_reuse_address(true)?;
+
+    if mode == Interception::Tproxy {
+        let r = match from {
+            SocketAddr::V4(_) => s.set_ip_transparent_v4(true),
+            SocketAddr::V6(_) => set_ipv6_transparent(&s),
+        };
+
+        r.map_err(|e| match e.raw_os_error() {
+            Some(libc::EPERM | libc::EACCES) => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "intercepting with TPROXY requires CAP_NET_ADMIN",
+            ),
+            _ => e,
+        })?;
+    }
+
+    s.set_nonblocking(true)?;
+    s.bind(&from.into())?;
+    s.listen(1024)?;
+
+    TcpListener::from_std(s.into())
+}
+
+fn set_ipv6_transparent(s: &Socket) -> io::Result<()> {
+    let on: libc::c_int = 1;
+
+    let r = unsafe {
+        libc::setsockopt(
+            s.as_raw_fd(),
+            libc::SOL_IPV6,
+            libc::IPV6_TRANSPARENT,
+            (&raw const on).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if r == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// The address an intercepted client originally connected to.
+pub fn original_dst(
+    stream: &TcpStream,
+    mode: Interception,
+    listener: SocketAddr,
+) -> io::Result<SocketAddr> {
+    let addr = match mode {
+        Interception::Tproxy => {
+            let addr = stream.local_addr()?;
+
+            // Forwarding a direct connection to the listener would loop.
+            let direct = addr.port() == listener.port()
+                && (listener.ip().is_unspecified() || addr.ip() == listener.ip());
+
+            if direct {
+                return Err(not_intercepted());
+            }
+
+            addr
+        }
+        Interception::Redirect => {
+            let s = SockRef::from(stream);
+
+            let addr = match stream.local_addr()?.ip().to_canonical() {
+                IpAddr::V4(_) => s.original_dst_v4(),
+                IpAddr::V6(_) => s.original_dst_v6(),
+            };
+
+            // Conntrack has no entry for connections that were not redirected.
+            let addr = addr.map_err(|e| match e.raw_os_error() {
+                Some(libc::ENOENT) => not_intercepted(),
+                _ => e,
+            })?;
+
+            addr.as_socket().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "original destination is not IP")
+            })?
+        }
+    };
+
+    Ok(SocketAddr::new(addr.ip().to_canonical(), addr.port()))
+}
+
+fn not_intercepted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "connection was not intercepted",
+    )
+}
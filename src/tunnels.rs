@@ -0,0 +1,34 @@
+use tokio::sync::watch;
+
+#[derive(Debug)]
+pub struct Tunnels(watch::Sender<usize>);
+
+impl Default for Tunnels {
+    fn default() -> Self {
+        Self(watch::Sender::new(0))
+    }
+}
+
+impl Tunnels {
+    pub fn open(&self) -> TunnelGuard<'_> {
+        self.0.send_modify(|x| *x += 1);
+        TunnelGuard(self)
+    }
+
+    pub fn count(&self) -> usize {
+        *self.0.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.0.subscribe()
+    }
+}
+
+#[derive(Debug)]
+pub struct TunnelGuard<'a>(&'a Tunnels);
+
+impl Drop for TunnelGuard<'_> {
+    fn drop(&mut self) {
+        self.0.0.send_modify(|x| *x -= 1);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+
+use crate::monitor::Probe;
+use crate::power::PowerState;
+use crate::upstream::{ConnectOpts, Upstream};
+use crate::waker::WakeContext;
+
+impl Upstream {
+    /// Wake the upstream up without connecting to it, unless its probe finds
+    /// that it is already awake.
+    ///
+    /// The upstream must have a probe, and is only considered to be up once
+    /// the probe succeeds.
+    pub async fn wake_up(
+        &self,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+        upstream: &HashMap<IpAddr, Upstream>,
+    ) -> io::Result<()> {
+        let probe = self
+            .probe
+            .as_ref()
+            .expect("waking up without connecting requires a probe");
+
+        if self.power.get() == PowerState::Asleep {
+            debug!("upstream is known to be asleep");
+        } else if probe.check(self.address).await.is_ok() {
+            self.power.set(PowerState::Awake);
+            return Ok(());
+        }
+
+        self.wake_once(opts, ctx, upstream, || {
+            self.wake_and_probe(probe, opts, ctx)
+        })
+        .await
+        .map(drop)
+    }
+
+    async fn wake_and_probe(
+        &self,
+        probe: &Probe,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+    ) -> io::Result<()> {
+        let mut attempts = 0;
+        let mut delays = opts.backoff.delays();
+
+        loop {
+            self.wake(ctx).await?;
+            self.waker.wait(opts.wait_for).await?;
+
+            match probe.check(self.address).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    attempts += 1;
+
+                    if attempts == opts.max_attempts.get() {
+                        debug!("max attempts reached, will not try again");
+                        return Err(e);
+                    } else {
+                        let delay = delays.next().expect("delays never end");
+
+                        warn!("upstream did not answer the probe: {}", display!(e));
+                        debug!("retrying in {}", display!(delay));
+                        sleep(delay).await;
+
+                        continue;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A socket connected to `to`, to relay a single client's datagrams from.
+pub async fn connect(to: SocketAddr) -> io::Result<UdpSocket> {
+    let local = match to {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(to).await?;
+    Ok(socket)
+}
+
+/// Drop datagrams until the client has been quiet for `idle`.
+pub async fn discard(datagrams: &mut mpsc::Receiver<Vec<u8>>, idle: Duration) {
+    while let Ok(Some(_)) = timeout(idle, datagrams.recv()).await {}
+}
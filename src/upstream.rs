@@ -1,40 +1,70 @@
+use std::collections::HashMap;
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZero;
 use std::time::Duration;
 
-use tokio::net::{TcpStream, UdpSocket};
-use tokio::time::sleep;
+use tokio::net::TcpStream;
+use tokio::time::{Instant, sleep, timeout, timeout_at};
 
-use crate::hardware_addr::HardwareAddr;
-use crate::util::DurationExt;
+use crate::access::Access;
+use crate::backoff::{Backoff, Strategy};
+use crate::depends::Dependency;
+use crate::flight::{Role, SingleFlight};
+use crate::heartbeat::Heartbeat;
+use crate::knock::Grants;
+use crate::limit::WakeLimit;
+use crate::monitor::Probe;
+use crate::power::{Power, PowerState};
+use crate::retry::RetryOn;
+use crate::schedule::Schedule;
+use crate::tunnels::Tunnels;
+use crate::waker::{Command, WakeContext, Waker};
 
 #[derive(Debug)]
 pub struct Upstream {
-    pub mac: HardwareAddr,
     pub address: IpAddr,
-    pub broadcast: IpAddr,
+    pub waker: Box<dyn Waker>,
+    pub wake_timeout: Duration,
+    pub sleep_cmd: Option<Command>,
+    pub idle_sleep: Option<Duration>,
+    pub heartbeat: Option<Heartbeat>,
+    pub probe: Option<Probe>,
+    pub access: Access,
+    pub wake_limit: WakeLimit,
+    pub grants: Option<Grants>,
+    pub schedule: Schedule,
+    pub requires: Vec<Dependency>,
+    pub power: Power,
+    pub flight: SingleFlight,
+    pub tunnels: Tunnels,
 }
 
 impl Upstream {
-    async fn wake(&self) -> io::Result<()> {
+    pub async fn wake(&self, ctx: &WakeContext) -> io::Result<()> {
         info!("waking upstream");
 
-        let mut packet = Vec::with_capacity(102);
-        packet.extend_from_slice(&[0xff; 6]);
-        for _ in 0..16 {
-            packet.extend_from_slice(self.mac.octets());
+        if !self.waker.can_hang() {
+            return self.waker.wake(ctx).await;
         }
 
-        let bind_on = match self.address {
-            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        timeout(self.wake_timeout, self.waker.wake(ctx))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "waking upstream timed out"))?
+    }
+
+    pub async fn sleep(&self) -> io::Result<()> {
+        let Some(cmd) = &self.sleep_cmd else {
+            return Ok(());
         };
 
-        let s = UdpSocket::bind((bind_on, 0)).await?;
-        s.set_broadcast(true)?;
+        info!("putting upstream to sleep");
+
+        timeout(self.wake_timeout, cmd.run(None))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "sleep command timed out"))??;
 
-        s.send_to(&packet, (self.broadcast, 9)).await?;
+        self.power.set(PowerState::Asleep);
         Ok(())
     }
 }
@@ -43,46 +73,182 @@ impl Upstream {
 pub struct ConnectOpts {
     pub wait_for: Duration,
     pub max_attempts: NonZero<u64>,
-    pub retry_delay: Duration,
-    pub retry_factor: f64,
+    pub backoff: Backoff,
+    pub deadline: Option<Duration>,
+    pub connect_timeout: Duration,
+    pub retry_on: Vec<RetryOn>,
+}
+
+impl Default for ConnectOpts {
+    fn default() -> Self {
+        Self {
+            wait_for: Duration::from_secs(0),
+            max_attempts: NonZero::new(5).expect("5 is not 0"),
+            backoff: Backoff {
+                strategy: Strategy::Exponential,
+                delay: Duration::from_secs(1),
+                factor: 2.0,
+                max_delay: None,
+            },
+            deadline: None,
+            connect_timeout: Duration::from_secs(10),
+            retry_on: RetryOn::ALL.to_vec(),
+        }
+    }
+}
+
+impl ConnectOpts {
+    fn retry_on(&self, e: &io::Error) -> Option<RetryOn> {
+        RetryOn::of(e).filter(|x| self.retry_on.contains(x))
+    }
 }
 
 impl Upstream {
-    pub async fn connect(&self, port: u16, opts: &ConnectOpts) -> io::Result<TcpStream> {
+    pub async fn connect(
+        &self,
+        port: u16,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+        upstream: &HashMap<IpAddr, Upstream>,
+    ) -> io::Result<TcpStream> {
+        match self.try_connect(port, opts).await? {
+            Some(x) => Ok(x),
+            None => self.wake_and_connect(port, opts, ctx, upstream).await,
+        }
+    }
+
+    /// Connect if the upstream is awake. Returns `None` if it is not.
+    pub async fn try_connect(
+        &self,
+        port: u16,
+        opts: &ConnectOpts,
+    ) -> io::Result<Option<TcpStream>> {
+        if self.power.get() == PowerState::Asleep {
+            debug!("upstream is known to be asleep");
+            return Ok(None);
+        }
+
+        match connect(SocketAddr::new(self.address, port), opts).await {
+            Ok(x) => {
+                self.power.set(PowerState::Awake);
+                Ok(Some(x))
+            }
+            Err(e) => match opts.retry_on(&e) {
+                Some(x) => {
+                    debug!("cannot connect to upstream: {x}");
+                    Ok(None)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Wake the upstream up and connect to it once it is up.
+    pub async fn wake_and_connect(
+        &self,
+        port: u16,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+        upstream: &HashMap<IpAddr, Upstream>,
+    ) -> io::Result<TcpStream> {
         let to = SocketAddr::new(self.address, port);
 
-        match TcpStream::connect(to).await {
-            Ok(x) => return Ok(x),
-            Err(e) if !is_retry_error(&e) => return Err(e),
-            Err(_) => {}
+        let woken = self
+            .wake_once(opts, ctx, upstream, || self.retry_connect(to, opts, ctx))
+            .await?;
+
+        match woken {
+            Some(x) => Ok(x),
+            None => connect(to, opts).await,
         }
+    }
 
+    /// Wake the dependencies and then the upstream up with `attempt`, or wait
+    /// for the wake that is already in progress.
+    ///
+    /// Returns what `attempt` returned, or `None` if the upstream was woken up
+    /// by another caller.
+    pub async fn wake_once<T, F>(
+        &self,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+        upstream: &HashMap<IpAddr, Upstream>,
+        attempt: impl FnOnce() -> F,
+    ) -> io::Result<Option<T>>
+    where
+        F: Future<Output = io::Result<T>>,
+    {
+        // Every client that wants the upstream awake counts against its own
+        // limit, even if it only waits for a wake in progress.
+        if let Some(client) = ctx.client {
+            self.wake_limit.take_client(client.ip())?;
+        }
+
+        match self.flight.join() {
+            Role::Leader(leader) => {
+                if let Err(e) = self.wake_limit.take() {
+                    let r = Err(e);
+                    leader.land(&r);
+                    return r;
+                }
+
+                let deadline = opts.deadline.map(|x| Instant::now() + x);
+
+                // A dependency that is not ready says nothing about this
+                // upstream, which is left as it was.
+                let r = match within(deadline, self.wake_dependencies(upstream)).await {
+                    Ok(()) => {
+                        let waking = self.power.waking();
+                        let r = within(deadline, attempt()).await;
+                        waking.finish(&r);
+                        r
+                    }
+                    Err(e) => Err(e),
+                };
+                leader.land(&r);
+
+                r.map(Some)
+            }
+
+            Role::Follower(follower) => {
+                debug!("waiting for wake already in progress");
+                follower.wait().await.map(|()| None)
+            }
+        }
+    }
+
+    async fn retry_connect(
+        &self,
+        to: SocketAddr,
+        opts: &ConnectOpts,
+        ctx: &WakeContext,
+    ) -> io::Result<TcpStream> {
         let mut attempts = 0;
-        let mut delay = opts.retry_delay;
+        let mut delays = opts.backoff.delays();
 
         loop {
-            self.wake().await?;
-            sleep(opts.wait_for).await;
+            self.wake(ctx).await?;
+            self.waker.wait(opts.wait_for).await?;
 
-            match TcpStream::connect(to).await {
+            match connect(to, opts).await {
                 Ok(x) => return Ok(x),
-                Err(e) if !is_retry_error(&e) => return Err(e),
                 Err(e) => {
+                    let Some(condition) = opts.retry_on(&e) else {
+                        return Err(e);
+                    };
+
                     attempts += 1;
 
                     if attempts == opts.max_attempts.get() {
                         debug!("max attempts reached, will not try again");
                         return Err(e);
                     } else {
-                        warn!("failed to connect to upstream: {}", display!(e));
+                        let delay = delays.next().expect("delays never end");
+
+                        warn!("failed to connect to upstream: {condition}");
                         debug!("retrying in {}", display!(delay));
                         sleep(delay).await;
 
-                        delay = delay.checked_mul_f64(opts.retry_factor).unwrap_or_else(|| {
-                            warn!("invalid retry factor");
-                            delay
-                        });
-
                         continue;
                     }
                 }
@@ -91,8 +257,29 @@ impl Upstream {
     }
 }
 
-fn is_retry_error(e: &io::Error) -> bool {
-    use io::ErrorKind::*;
+// Run `f` until `deadline`, if there is one.
+async fn within<T>(
+    deadline: Option<Instant>,
+    f: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match deadline {
+        Some(x) => timeout_at(x, f).await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "deadline for waking upstream passed",
+            ))
+        }),
+        None => f.await,
+    }
+}
+
+pub fn not_awake() -> io::Error {
+    io::Error::new(io::ErrorKind::HostUnreachable, "upstream is not awake")
+}
 
-    matches!(e.kind(), HostUnreachable | ConnectionRefused)
+/// Connect to `to` within 'connect-timeout'.
+pub async fn connect(to: SocketAddr, opts: &ConnectOpts) -> io::Result<TcpStream> {
+    timeout(opts.connect_timeout, TcpStream::connect(to))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))?
 }
@@ -1,5 +1,9 @@
+use std::io;
+use std::num::ParseIntError;
 use std::time::Duration;
 
+use thiserror::Error;
+
 pub trait DurationExt {
     fn checked_mul_f64(self, rhs: f64) -> Option<Duration>;
 }
@@ -12,3 +16,55 @@ impl DurationExt for Duration {
         Duration::try_from_secs_f64(new).ok()
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UnescapeError {
+    #[error("unterminated escape sequence")]
+    Unterminated,
+
+    #[error("unknown escape sequence '\\{0}'")]
+    Unknown(char),
+
+    #[error("invalid hex escape: {0}")]
+    InvalidHex(ParseIntError),
+}
+
+// Supports \\, \r, \n, \t, \0 and \xNN.
+pub fn unescape(s: &str) -> Result<Vec<u8>, UnescapeError> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next().ok_or(UnescapeError::Unterminated)? {
+            '\\' => out.push(b'\\'),
+            'r' => out.push(b'\r'),
+            'n' => out.push(b'\n'),
+            't' => out.push(b'\t'),
+            '0' => out.push(0),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let x = u8::from_str_radix(&hex, 16).map_err(UnescapeError::InvalidHex)?;
+                out.push(x);
+            }
+            x => return Err(UnescapeError::Unknown(x)),
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn getrandom(buf: &mut [u8]) -> io::Result<()> {
+    let n = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+
+    match usize::try_from(n) {
+        Ok(n) if n == buf.len() => Ok(()),
+        Ok(_) => Err(io::Error::other("short read from getrandom")),
+        Err(_) => Err(io::Error::last_os_error()),
+    }
+}
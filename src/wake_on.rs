@@ -0,0 +1,134 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::timeout;
+
+/// Require clients to send some data before their upstream is woken up.
+#[derive(Debug, Clone)]
+pub struct WakeOn {
+    pub min_bytes: usize,
+    pub prefix: Vec<u8>,
+    pub timeout: Duration,
+}
+
+impl WakeOn {
+    /// Read from `client` until it has sent enough to wake the upstream.
+    ///
+    /// Returns everything that was read so that it can be replayed to the
+    /// upstream.
+    pub async fn wait(&self, client: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+        let need = self.min_bytes.max(self.prefix.len());
+        let mut data = Vec::with_capacity(need);
+
+        let read = async {
+            let mut buf = [0u8; 4096];
+
+            while data.len() < need {
+                let n = client.read(&mut buf).await?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("client disconnected after sending {} bytes", data.len()),
+                    ));
+                }
+
+                data.extend_from_slice(&buf[..n]);
+
+                let len = data.len().min(self.prefix.len());
+                if data[..len] != self.prefix[..len] {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "client data does not match the expected prefix",
+                    ));
+                }
+            }
+
+            Ok(())
+        };
+
+        timeout(self.timeout, read).await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "client did not send enough data in time",
+            )
+        })??;
+
+        debug!("client sent {} bytes", data.len());
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, duplex};
+
+    use super::*;
+
+    fn wake_on(min_bytes: usize, prefix: &[u8]) -> WakeOn {
+        WakeOn {
+            min_bytes,
+            prefix: prefix.to_vec(),
+            timeout: Duration::from_millis(50),
+        }
+    }
+
+    #[tokio::test]
+    async fn enough_bytes() {
+        let data = wake_on(4, b"").wait(&mut &b"hello"[..]).await.unwrap();
+
+        assert_eq!(data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn bytes_over_several_reads() {
+        let (mut client, mut server) = duplex(64);
+
+        let w = wake_on(4, b"");
+        let wait = tokio::spawn(async move { w.wait(&mut server).await });
+
+        client.write_all(b"he").await.unwrap();
+        client.write_all(b"llo").await.unwrap();
+
+        assert_eq!(wait.await.unwrap().unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn matching_prefix() {
+        let data = wake_on(0, b"SSH-")
+            .wait(&mut &b"SSH-2.0-OpenSSH"[..])
+            .await
+            .unwrap();
+
+        assert_eq!(data, b"SSH-2.0-OpenSSH");
+    }
+
+    #[tokio::test]
+    async fn mismatched_prefix() {
+        let e = wake_on(0, b"SSH-")
+            .wait(&mut &b"GET / HTTP/1.1"[..])
+            .await
+            .unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn client_closes_early() {
+        let e = wake_on(8, b"").wait(&mut &b"hello"[..]).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        let e = wake_on(0, b"SSH-").wait(&mut &b"SS"[..]).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn too_slow() {
+        let (mut client, mut server) = duplex(64);
+        client.write_all(b"he").await.unwrap();
+
+        let e = wake_on(4, b"").wait(&mut server).await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+}
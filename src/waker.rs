@@ -0,0 +1,43 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+mod command;
+mod http;
+mod magic;
+
+pub use self::command::Command;
+pub use self::http::{Http, HttpMethod, ParseUrlError};
+pub use self::magic::{MagicPacket, WakeMode};
+
+pub type WakeFuture<'a> = Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WakeContext {
+    /// The client whose connection triggered the wake, if any.
+    pub client: Option<SocketAddr>,
+}
+
+/// A way of powering on an upstream.
+pub trait Waker: fmt::Debug + Send + Sync {
+    fn wake(&self, ctx: &WakeContext) -> WakeFuture<'_>;
+
+    /// Whether waking up may never finish, like a command that hangs, and so
+    /// needs to be limited by 'wake-timeout'.
+    fn can_hang(&self) -> bool {
+        true
+    }
+
+    /// Wait for `duration` while the upstream is coming up.
+    fn wait(&self, duration: Duration) -> WakeFuture<'_> {
+        Box::pin(async move {
+            sleep(duration).await;
+            Ok(())
+        })
+    }
+}
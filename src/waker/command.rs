@@ -0,0 +1,75 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::process::Stdio;
+
+use miniarg::split_args::SplitArgs;
+use tokio::process;
+
+use super::{WakeContext, WakeFuture, Waker};
+use crate::hardware_addr::HardwareAddr;
+
+/// Run a program with details about the upstream in its environment.
+///
+/// The program is killed if the future running it is dropped, which is how
+/// timeouts stop it.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub argv: Vec<String>,
+    pub address: IpAddr,
+    pub mac: Option<HardwareAddr>,
+}
+
+impl Command {
+    pub fn new(cmdline: &str, address: IpAddr, mac: Option<HardwareAddr>) -> Option<Self> {
+        let argv: Vec<String> = SplitArgs::new(cmdline).map(str::to_owned).collect();
+
+        if argv.is_empty() {
+            return None;
+        }
+
+        Some(Self { argv, address, mac })
+    }
+
+    pub async fn run(&self, client: Option<SocketAddr>) -> io::Result<()> {
+        let program = &self.argv[0];
+
+        let mut cmd = process::Command::new(program);
+        cmd.args(&self.argv[1..])
+            .env("WOLLY_UPSTREAM", self.address.to_string())
+            .env(
+                "WOLLY_MAC",
+                self.mac.map(|x| format!("{x:?}")).unwrap_or_default(),
+            )
+            .env(
+                "WOLLY_CLIENT",
+                client.map(|x| x.to_string()).unwrap_or_default(),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        debug!("running '{program}'");
+        let output = cmd
+            .output()
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to run '{program}': {e}")))?;
+
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            info!("{program}: {line}");
+        }
+
+        if !output.status.success() {
+            return Err(io::Error::other(format!("'{program}' {}", output.status)));
+        }
+
+        debug!("'{program}' {}", output.status);
+        Ok(())
+    }
+}
+
+impl Waker for Command {
+    fn wake(&self, ctx: &WakeContext) -> WakeFuture<'_> {
+        Box::pin(self.run(ctx.client))
+    }
+}
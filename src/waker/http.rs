@@ -0,0 +1,137 @@
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{WakeContext, WakeFuture, Waker};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseUrlError {
+    #[error("only 'http://' URLs are supported")]
+    UnsupportedScheme,
+
+    #[error("missing host")]
+    MissingHost,
+
+    #[error("invalid port: {0}")]
+    InvalidPort(ParseIntError),
+}
+
+impl FromStr for HttpUrl {
+    type Err = ParseUrlError;
+
+    // Syntax: http://<host>[:<port>][/<path>]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseUrlError::*;
+
+        let s = s.strip_prefix("http://").ok_or(UnsupportedScheme)?;
+
+        let (authority, path) = match s.find('/') {
+            Some(i) => s.split_at(i),
+            None => (s, "/"),
+        };
+
+        // Bracketed IPv6 hosts contain ':' too.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.ends_with(']') => {
+                (host, port.parse().map_err(InvalidPort)?)
+            }
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(MissingHost);
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpMethod {
+    Get,
+    Post,
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Get => f.write_str("GET"),
+            Self::Post => f.write_str("POST"),
+        }
+    }
+}
+
+/// Power on an upstream by requesting a URL, like the API of a smart plug.
+#[derive(Debug)]
+pub struct Http {
+    pub url: HttpUrl,
+    pub method: HttpMethod,
+}
+
+impl Waker for Http {
+    fn wake(&self, _: &WakeContext) -> WakeFuture<'_> {
+        Box::pin(self.request())
+    }
+}
+
+impl Http {
+    async fn request(&self) -> io::Result<()> {
+        let HttpUrl { host, port, path } = &self.url;
+        let host_ip = host.trim_start_matches('[').trim_end_matches(']');
+
+        let mut s = TcpStream::connect((host_ip, *port)).await?;
+
+        let request = format!(
+            "{} {path} HTTP/1.1\r\n\
+             Host: {host}:{port}\r\n\
+             User-Agent: wolly\r\n\
+             Content-Length: 0\r\n\
+             Connection: close\r\n\
+             \r\n",
+            self.method
+        );
+        s.write_all(request.as_bytes()).await?;
+
+        let mut status_line = String::new();
+        BufReader::new(s).read_line(&mut status_line).await?;
+
+        // HTTP/1.1 200 OK
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+
+        if !(200..300).contains(&status) {
+            return Err(io::Error::other(format!(
+                "{} responded with '{}'",
+                self.url,
+                status_line.trim_end()
+            )));
+        }
+
+        Ok(())
+    }
+}
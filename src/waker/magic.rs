@@ -0,0 +1,167 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::num::NonZero;
+use std::time::Duration;
+
+use socket2::SockRef;
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, sleep, sleep_until};
+
+use super::{WakeContext, WakeFuture, Waker};
+use crate::ether;
+use crate::hardware_addr::HardwareAddr;
+use crate::iface::Interface;
+use crate::secureon::Password;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeMode {
+    Udp,
+    Ether,
+}
+
+#[derive(Debug)]
+pub struct MagicPacket {
+    pub mac: HardwareAddr,
+    pub address: IpAddr,
+    pub broadcast: Vec<IpAddr>,
+    pub iface: Option<Interface>,
+    pub source: Option<IpAddr>,
+    pub mode: WakeMode,
+    pub password: Option<Password>,
+    pub port: u16,
+    pub repeat: NonZero<u32>,
+    pub interval: Duration,
+    pub resend: Option<Duration>,
+}
+
+impl Waker for MagicPacket {
+    fn wake(&self, _: &WakeContext) -> WakeFuture<'_> {
+        Box::pin(self.send())
+    }
+
+    // Sending takes 'wake-repeat' × 'wake-interval' and cannot hang.
+    fn can_hang(&self) -> bool {
+        false
+    }
+
+    // Wait for `duration` while resending the magic packet every
+    // 'wake-resend' seconds.
+    fn wait(&self, duration: Duration) -> WakeFuture<'_> {
+        Box::pin(async move {
+            let Some(every) = self.resend else {
+                sleep(duration).await;
+                return Ok(());
+            };
+
+            let deadline = Instant::now() + duration;
+
+            loop {
+                let next = Instant::now() + every;
+                if next >= deadline {
+                    sleep_until(deadline).await;
+                    return Ok(());
+                }
+
+                sleep_until(next).await;
+
+                debug!("resending magic packet");
+                self.send().await?;
+            }
+        })
+    }
+}
+
+impl MagicPacket {
+    async fn send(&self) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(108);
+        packet.extend_from_slice(&[0xff; 6]);
+        for _ in 0..16 {
+            packet.extend_from_slice(self.mac.octets());
+        }
+        if let Some(password) = &self.password {
+            packet.extend_from_slice(password.octets());
+        }
+
+        for i in 0..self.repeat.get() {
+            if i != 0 {
+                sleep(self.interval).await;
+            }
+
+            match self.mode {
+                WakeMode::Udp => self.send_udp(&packet).await?,
+                WakeMode::Ether => {
+                    let iface = self
+                        .iface
+                        .as_ref()
+                        .expect("ether mode requires an interface");
+                    ether::send(iface, &packet)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_udp(&self, packet: &[u8]) -> io::Result<()> {
+        let bind_on = match (self.source, self.address) {
+            (Some(x), _) => x,
+            (None, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (None, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        let s = UdpSocket::bind((bind_on, 0)).await?;
+
+        if let Some(iface) = &self.iface {
+            iface.bind(SockRef::from(&s), bind_on.is_ipv6())?;
+        }
+
+        let mut last_error = None;
+        let mut sent = false;
+
+        for &broadcast in &self.broadcast {
+            match self.send_udp_to(&s, packet, broadcast).await {
+                Ok(()) => sent = true,
+                Err(e) => {
+                    warn!(
+                        "failed to send magic packet to {}: {}",
+                        display!(broadcast),
+                        display!(e)
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if !sent => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn send_udp_to(&self, s: &UdpSocket, packet: &[u8], to: IpAddr) -> io::Result<()> {
+        let to = match to {
+            IpAddr::V4(x) => {
+                s.set_broadcast(true)?;
+                SocketAddr::from((x, self.port))
+            }
+
+            // IPv6 has no broadcast, the packet goes to a multicast group
+            // instead which needs to be scoped to an interface.
+            IpAddr::V6(x) => {
+                let scope_id = match &self.iface {
+                    Some(iface) => iface.index()?,
+                    None => 0,
+                };
+
+                if scope_id != 0 {
+                    SockRef::from(s).set_multicast_if_v6(scope_id)?;
+                }
+
+                SocketAddr::V6(SocketAddrV6::new(x, self.port, 0, scope_id))
+            }
+        };
+
+        s.send_to(packet, to).await?;
+        Ok(())
+    }
+}